@@ -0,0 +1,94 @@
+//! Runs the expensive processing natively, for batch jobs. Usage:
+//!
+//!     cargo run --release --bin cli -- snapshot input.osm output.bin
+//!     cargo run --release --bin cli -- flatgeobuf input.osm output_directory
+//!     cargo run --release --bin cli -- svg input.osm output.svg
+//!     cargo run --release --bin cli -- png input.osm output.png
+//!     cargo run --release --bin cli -- debug-png input.osm road|intersection <id> output.png
+//!     cargo run --release --bin cli -- tiles input.osm output_directory <min_zoom> <max_zoom>
+//!
+//! The input of any command can be an osm.xml or osm.pbf file, or a snapshot.
+
+use std::process::exit;
+
+use anyhow::Result;
+use backend::{IntersectionID, MapModel, RoadID};
+
+const DEBUG_PNG_WIDTH: f64 = 800.0;
+
+fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if let Err(err) = run(&args) {
+        eprintln!("{err}");
+        exit(1);
+    }
+}
+
+fn run(args: &[String]) -> Result<()> {
+    match args {
+        [cmd, input, output] if cmd == "snapshot" => {
+            let map = load(input)?;
+            std::fs::write(output, map.save_snapshot()?)?;
+            println!("Wrote {output}");
+            Ok(())
+        }
+        [cmd, input, output_dir] if cmd == "flatgeobuf" => {
+            let map = load(input)?;
+            std::fs::create_dir_all(output_dir)?;
+            for (name, bytes) in map.export_all_flatgeobuf()? {
+                let path = format!("{output_dir}/{name}.fgb");
+                std::fs::write(&path, bytes)?;
+                println!("Wrote {path}");
+            }
+            Ok(())
+        }
+        [cmd, input, output] if cmd == "svg" => {
+            let map = load(input)?;
+            std::fs::write(output, map.to_svg()?)?;
+            println!("Wrote {output}");
+            Ok(())
+        }
+        [cmd, input, output] if cmd == "png" => {
+            let map = load(input)?;
+            std::fs::write(output, map.to_png()?)?;
+            println!("Wrote {output}");
+            Ok(())
+        }
+        [cmd, input, kind, id, output] if cmd == "debug-png" => {
+            let map = load(input)?;
+            let id: usize = id.parse()?;
+            let bytes = match kind.as_str() {
+                "road" => map.road_width_debug_png(RoadID(id), DEBUG_PNG_WIDTH)?,
+                "intersection" => {
+                    map.intersection_geometry_debug_png(IntersectionID(id), DEBUG_PNG_WIDTH)?
+                }
+                _ => anyhow::bail!("debug-png needs road or intersection, not {kind}"),
+            };
+            std::fs::write(output, bytes)?;
+            println!("Wrote {output}");
+            Ok(())
+        }
+        [cmd, input, output_dir, min_zoom, max_zoom] if cmd == "tiles" => {
+            let map = load(input)?;
+            let mut count = 0;
+            map.write_vector_tiles(min_zoom.parse()?, max_zoom.parse()?, |z, x, y, bytes| {
+                let dir = format!("{output_dir}/{z}/{x}");
+                std::fs::create_dir_all(&dir)?;
+                std::fs::write(format!("{dir}/{y}.pbf"), bytes)?;
+                count += 1;
+                Ok(())
+            })?;
+            println!("Wrote {count} tiles to {output_dir}");
+            Ok(())
+        }
+        _ => {
+            anyhow::bail!(
+                "Usage: cli snapshot <input> <output.bin>\n       cli flatgeobuf <input> <output_directory>\n       cli svg <input> <output.svg>\n       cli png <input> <output.png>\n       cli debug-png <input> road|intersection <id> <output.png>\n       cli tiles <input> <output_directory> <min_zoom> <max_zoom>"
+            );
+        }
+    }
+}
+
+fn load(path: &str) -> Result<MapModel> {
+    MapModel::load(&std::fs::read(path)?)
+}
@@ -0,0 +1,137 @@
+use std::collections::HashMap;
+
+use geo::EuclideanLength;
+
+use crate::{Intersection, IntersectionID, Road, RoadID};
+
+/// Assigns every road and intersection a connected component. Components are numbered by
+/// decreasing number of intersections, so the largest is always 0.
+pub fn assign_components(roads: &mut [Road], intersections: &mut [Intersection]) {
+    let labels = label_components(
+        intersections.len(),
+        roads.iter().map(|r| (r.src_i.0, r.dst_i.0)),
+    );
+    for i in intersections {
+        i.component = labels[i.id.0];
+    }
+    for r in roads {
+        r.component = labels[r.src_i.0];
+    }
+}
+
+/// Removes every connected component with too few roads or too little total road length, then
+/// renumbers the surviving roads, intersections and components. Components must already be
+/// assigned, and keep their order, so the largest surviving one is still 0.
+pub fn remove_small_components(
+    roads: Vec<Road>,
+    intersections: Vec<Intersection>,
+    min_roads: usize,
+    min_length: f64,
+) -> (Vec<Road>, Vec<Intersection>) {
+    let mut count_per_component: HashMap<usize, usize> = HashMap::new();
+    let mut length_per_component: HashMap<usize, f64> = HashMap::new();
+    for r in &roads {
+        *count_per_component.entry(r.component).or_insert(0) += 1;
+        *length_per_component.entry(r.component).or_insert(0.0) += r.linestring.euclidean_length();
+    }
+    let keep = |component: usize| {
+        count_per_component.get(&component).cloned().unwrap_or(0) >= min_roads
+            && length_per_component.get(&component).cloned().unwrap_or(0.0) >= min_length
+    };
+
+    let mut kept_components: Vec<usize> = intersections
+        .iter()
+        .map(|i| i.component)
+        .filter(|c| keep(*c))
+        .collect();
+    kept_components.sort();
+    kept_components.dedup();
+    let component_mapping: HashMap<usize, usize> = kept_components
+        .into_iter()
+        .enumerate()
+        .map(|(new, old)| (old, new))
+        .collect();
+
+    let mut intersection_mapping = HashMap::new();
+    let mut kept_intersections = Vec::new();
+    for mut i in intersections {
+        if !keep(i.component) {
+            continue;
+        }
+        let id = IntersectionID(kept_intersections.len());
+        intersection_mapping.insert(i.id, id);
+        i.id = id;
+        i.component = component_mapping[&i.component];
+        kept_intersections.push(i);
+    }
+
+    let mut road_mapping = HashMap::new();
+    let mut kept_roads = Vec::new();
+    for mut r in roads {
+        if !keep(r.component) {
+            continue;
+        }
+        let id = RoadID(kept_roads.len());
+        road_mapping.insert(r.id, id);
+        r.id = id;
+        r.src_i = intersection_mapping[&r.src_i];
+        r.dst_i = intersection_mapping[&r.dst_i];
+        r.component = component_mapping[&r.component];
+        kept_roads.push(r);
+    }
+
+    for i in &mut kept_intersections {
+        // Every road touching a kept intersection is in the same component, so it's kept too
+        i.roads = i.roads.iter().map(|r| road_mapping[r]).collect();
+    }
+
+    info!(
+        "Kept {} roads and {} intersections after removing small components",
+        kept_roads.len(),
+        kept_intersections.len()
+    );
+    (kept_roads, kept_intersections)
+}
+
+/// Given a number of nodes and links between them, returns the component of every node,
+/// numbered by decreasing size.
+pub fn label_components(
+    num_nodes: usize,
+    links: impl Iterator<Item = (usize, usize)>,
+) -> Vec<usize> {
+    // Union-find
+    let mut parent: Vec<usize> = (0..num_nodes).collect();
+    fn find(parent: &mut [usize], mut x: usize) -> usize {
+        while parent[x] != x {
+            parent[x] = parent[parent[x]];
+            x = parent[x];
+        }
+        x
+    }
+    for (a, b) in links {
+        let root_a = find(&mut parent, a);
+        let root_b = find(&mut parent, b);
+        if root_a != root_b {
+            parent[root_a] = root_b;
+        }
+    }
+
+    let roots: Vec<usize> = (0..num_nodes).map(|x| find(&mut parent, x)).collect();
+    let mut sizes: HashMap<usize, usize> = HashMap::new();
+    for root in &roots {
+        *sizes.entry(*root).or_insert(0) += 1;
+    }
+    // Biggest first, breaking ties by the root for determinism
+    let mut ordered: Vec<(usize, usize)> = sizes.into_iter().collect();
+    ordered.sort_by_key(|(root, size)| (std::cmp::Reverse(*size), *root));
+    let root_to_component: HashMap<usize, usize> = ordered
+        .into_iter()
+        .enumerate()
+        .map(|(component, (root, _))| (root, component))
+        .collect();
+
+    roots
+        .into_iter()
+        .map(|root| root_to_component[&root])
+        .collect()
+}
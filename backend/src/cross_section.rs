@@ -0,0 +1,343 @@
+use geo::{
+    BoundingRect, Closest, ClosestPoint, Coord, EuclideanDistance, LineInterpolatePoint,
+    LineString, Point, Polygon,
+};
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+use utils::Tags;
+
+use crate::math::{offset_band, project_away};
+use crate::{IntersectionID, MapModel, Road};
+
+/// When a sidewalk's width isn't tagged
+const DEFAULT_SIDEWALK_WIDTH: f64 = 2.0;
+/// When the carriageway's width has to be guessed from the number of lanes
+const DEFAULT_LANE_WIDTH: f64 = 3.0;
+/// How far a separately mapped sidewalk's direction can differ from the road's
+const MAX_SIDEWALK_ANGLE_DEGREES: f64 = 30.0;
+
+/// Splits the corridor found by `find_road_width` into the carriageway and sidewalk bands. All
+/// distances are from the centerline, in meters.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CrossSection {
+    pub carriageway_left: f64,
+    pub carriageway_right: f64,
+    pub carriageway: Polygon,
+    pub left_sidewalk: Option<SidewalkBand>,
+    pub right_sidewalk: Option<SidewalkBand>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct SidewalkBand {
+    pub source: SidewalkSource,
+    /// The edge next to the carriageway
+    pub inner: f64,
+    /// The edge next to buildings
+    pub outer: f64,
+    pub polygon: Polygon,
+}
+
+#[derive(Clone, Copy, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "snake_case")]
+pub enum SidewalkSource {
+    /// From `sidewalk=*` tags on the road
+    Tagged,
+    /// A `highway=footway` + `footway=sidewalk` way running alongside the road
+    Separate,
+}
+
+/// A `footway=sidewalk` way found near the road
+#[derive(Clone, Copy)]
+struct SeparateSidewalk {
+    /// From the road's centerline
+    dist: f64,
+    width: f64,
+}
+
+/// What the road's own tags say about one side
+#[derive(PartialEq)]
+enum SidewalkTag {
+    Yes,
+    Separate,
+    No,
+    Unknown,
+}
+
+pub fn find_all(map: &mut MapModel) {
+    let results: Vec<Option<CrossSection>> =
+        map.roads.iter().map(|r| cross_section(map, r)).collect();
+    for (road, cross_section) in map.roads.iter_mut().zip(results) {
+        road.cross_section = cross_section;
+    }
+}
+
+fn cross_section(map: &MapModel, road: &Road) -> Option<CrossSection> {
+    if is_footway(&road.tags) {
+        return None;
+    }
+    let corridor_left = road.max_left_width?;
+    let corridor_right = road.max_right_width?;
+    let (separate_left, separate_right) = find_separate_sidewalks(map, road);
+
+    let mut bands = Vec::new();
+    for (left, corridor, limited, separate) in [
+        (
+            true,
+            corridor_left,
+            road.left_limited_by.is_some(),
+            separate_left,
+        ),
+        (
+            false,
+            corridor_right,
+            road.right_limited_by.is_some(),
+            separate_right,
+        ),
+    ] {
+        let side = if left { "left" } else { "right" };
+        let tag = sidewalk_tag(&road.tags, side);
+
+        let band = match (tag, separate) {
+            (SidewalkTag::No, _) => None,
+            (
+                SidewalkTag::Separate | SidewalkTag::Unknown,
+                Some(SeparateSidewalk { dist, width }),
+            ) => {
+                let inner = (dist - width / 2.0).max(0.0);
+                // Without anything limiting the corridor, it's just the default probe length
+                let outer = if limited {
+                    corridor
+                } else {
+                    (dist + width / 2.0).min(corridor)
+                };
+                Some((SidewalkSource::Separate, inner, outer))
+            }
+            (SidewalkTag::Yes, _) => {
+                let width = tagged_sidewalk_width(&road.tags, side);
+                let (inner, outer) = if limited {
+                    ((corridor - width).max(0.0), corridor)
+                } else {
+                    let carriageway = carriageway_half_width(&road.tags).min(corridor);
+                    (carriageway, (carriageway + width).min(corridor))
+                };
+                Some((SidewalkSource::Tagged, inner, outer))
+            }
+            (SidewalkTag::Separate | SidewalkTag::Unknown, None) => None,
+        };
+
+        bands.push(band.and_then(|(source, inner, outer)| {
+            let sign = if left { -1.0 } else { 1.0 };
+            let polygon = offset_band(&road.linestring, sign * inner, sign * outer)?;
+            Some(SidewalkBand {
+                source,
+                inner,
+                outer,
+                polygon,
+            })
+        }));
+    }
+    let right_sidewalk = bands.pop().unwrap();
+    let left_sidewalk = bands.pop().unwrap();
+
+    // Without a sidewalk, the carriageway fills the corridor only when something limits it
+    let unlimited = carriageway_half_width(&road.tags);
+    let carriageway_left = match (&left_sidewalk, road.left_limited_by.is_some()) {
+        (Some(band), _) => band.inner,
+        (None, true) => corridor_left,
+        (None, false) => unlimited.min(corridor_left),
+    };
+    let carriageway_right = match (&right_sidewalk, road.right_limited_by.is_some()) {
+        (Some(band), _) => band.inner,
+        (None, true) => corridor_right,
+        (None, false) => unlimited.min(corridor_right),
+    };
+    let carriageway = offset_band(&road.linestring, -carriageway_left, carriageway_right)?;
+
+    Some(CrossSection {
+        carriageway_left,
+        carriageway_right,
+        carriageway,
+        left_sidewalk,
+        right_sidewalk,
+    })
+}
+
+/// Finds the closest separately mapped sidewalk on each side of the road, within its corridor.
+fn find_separate_sidewalks(
+    map: &MapModel,
+    road: &Road,
+) -> (Option<SeparateSidewalk>, Option<SeparateSidewalk>) {
+    let mut left: Option<SeparateSidewalk> = None;
+    let mut right: Option<SeparateSidewalk> = None;
+
+    let Some(bbox) = road.polygon.as_ref().and_then(|p| p.bounding_rect()) else {
+        return (None, None);
+    };
+    let (candidates, _, _) = map.index.bbox_candidates(bbox);
+    for other in candidates.into_iter().map(|r| &map.roads[r.0]) {
+        if other.id == road.id
+            || !other.tags.is("highway", "footway")
+            || !other.tags.is("footway", "sidewalk")
+        {
+            continue;
+        }
+        let Some(midpoint) = other.linestring.line_interpolate_point(0.5) else {
+            continue;
+        };
+        let Some((dist, angle, is_left)) = locate(road, midpoint) else {
+            continue;
+        };
+        let Some(other_angle) = overall_angle(&other.linestring) else {
+            continue;
+        };
+        let diff = (angle - other_angle).rem_euclid(180.0);
+        if diff.min(180.0 - diff) > MAX_SIDEWALK_ANGLE_DEGREES {
+            continue;
+        }
+
+        let (side, limit) = if is_left {
+            (&mut left, road.max_left_width.unwrap_or(0.0))
+        } else {
+            (&mut right, road.max_right_width.unwrap_or(0.0))
+        };
+        if dist > limit {
+            continue;
+        }
+        if side.map(|best| dist < best.dist).unwrap_or(true) {
+            let width = other
+                .tags
+                .get("width")
+                .and_then(|w| parse_meters(w))
+                .unwrap_or(DEFAULT_SIDEWALK_WIDTH);
+            *side = Some(SeparateSidewalk { dist, width });
+        }
+    }
+    (left, right)
+}
+
+/// Finds the closest point on the road to `pt`. Returns the distance, the road's direction there
+/// in degrees, and whether `pt` is on the left. Points beyond either end of the road don't count.
+fn locate(road: &Road, pt: Point) -> Option<(f64, f64, bool)> {
+    let first = road.linestring.0.first()?;
+    let last = road.linestring.0.last()?;
+    let mut best: Option<(f64, f64, bool)> = None;
+    for line in road.linestring.lines() {
+        let closest = match line.closest_point(&pt) {
+            Closest::Intersection(p) | Closest::SinglePoint(p) => p,
+            Closest::Indeterminate => continue,
+        };
+        let dist = closest.euclidean_distance(&pt);
+        if best.map(|(best, _, _)| dist < best).unwrap_or(true) {
+            let angle = line.dy().atan2(line.dx()).to_degrees();
+            // Probes to the left go this way; see find_road_width
+            let left_normal = project_away(Coord { x: 0.0, y: 0.0 }, angle - 90.0, 1.0);
+            let offset = pt.0 - closest.0;
+            let is_left = offset.x * left_normal.x + offset.y * left_normal.y > 0.0;
+            let at_end = closest.0 == *first || closest.0 == *last;
+            best = Some((if at_end { f64::INFINITY } else { dist }, angle, is_left));
+        }
+    }
+    best.filter(|(dist, _, _)| dist.is_finite())
+}
+
+fn overall_angle(linestring: &LineString) -> Option<f64> {
+    let first = linestring.0.first()?;
+    let last = linestring.0.last()?;
+    if first == last {
+        return None;
+    }
+    Some((last.y - first.y).atan2(last.x - first.x).to_degrees())
+}
+
+fn sidewalk_tag(tags: &Tags, side: &str) -> SidewalkTag {
+    let value = tags
+        .get(&format!("sidewalk:{side}"))
+        .or_else(|| tags.get("sidewalk:both"))
+        .map(|x| x.as_str());
+    let value = match value {
+        Some(value) => value,
+        None => match tags.get("sidewalk").map(|x| x.as_str()) {
+            Some("both") => "yes",
+            Some("left") => {
+                if side == "left" {
+                    "yes"
+                } else {
+                    "no"
+                }
+            }
+            Some("right") => {
+                if side == "right" {
+                    "yes"
+                } else {
+                    "no"
+                }
+            }
+            Some(value) => value,
+            None => "",
+        },
+    };
+    match value {
+        "yes" => SidewalkTag::Yes,
+        "separate" => SidewalkTag::Separate,
+        "no" | "none" => SidewalkTag::No,
+        _ => SidewalkTag::Unknown,
+    }
+}
+
+fn tagged_sidewalk_width(tags: &Tags, side: &str) -> f64 {
+    [
+        format!("sidewalk:{side}:width"),
+        "sidewalk:both:width".to_string(),
+        "sidewalk:width".to_string(),
+    ]
+    .iter()
+    .find_map(|key| tags.get(key).and_then(|w| parse_meters(w)))
+    .unwrap_or(DEFAULT_SIDEWALK_WIDTH)
+}
+
+/// Half the carriageway's width, from the `width` tag or the number of lanes
+fn carriageway_half_width(tags: &Tags) -> f64 {
+    if let Some(width) = tags.get("width").and_then(|w| parse_meters(w)) {
+        return width / 2.0;
+    }
+    let default_lanes = if tags.is("oneway", "yes") { 1 } else { 2 };
+    let lanes = tags
+        .get("lanes")
+        .and_then(|x| x.parse::<usize>().ok())
+        .unwrap_or(default_lanes);
+    (lanes as f64) * DEFAULT_LANE_WIDTH / 2.0
+}
+
+/// Footways, paths and similar, as opposed to roads with a carriageway
+pub fn is_footway(tags: &Tags) -> bool {
+    tags.is_any(
+        "highway",
+        vec![
+            "footway",
+            "path",
+            "cycleway",
+            "bridleway",
+            "steps",
+            "pedestrian",
+        ],
+    )
+}
+
+/// How many roads with a carriageway meet at an intersection, ignoring footways and paths
+pub fn num_carriageways(map: &MapModel, i: IntersectionID) -> usize {
+    map.intersections[i.0]
+        .roads
+        .iter()
+        .filter(|r| !is_footway(&map.roads[r.0].tags))
+        .count()
+}
+
+/// Parses widths like "3", "3.5" or "3.5 m"
+pub fn parse_meters(value: &str) -> Option<f64> {
+    value
+        .trim()
+        .trim_end_matches('m')
+        .trim()
+        .parse::<f64>()
+        .ok()
+}
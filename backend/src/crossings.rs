@@ -0,0 +1,185 @@
+use std::collections::HashSet;
+
+use geo::{Coord, EuclideanLength, LineString, MultiPolygon, Polygon};
+use osm_reader::NodeID;
+
+use crate::cross_section::{is_footway, num_carriageways};
+use crate::intersection_geometry::intersection_polygon;
+use crate::math::{first_crossing, point_and_angle_at, project_away};
+use crate::nodes::NodeKind;
+use crate::{MapModel, Road, RoadID};
+
+/// How far a crosswalk extends along the road, in meters
+const CROSSWALK_LENGTH: f64 = 3.0;
+const STRIPE_WIDTH: f64 = 0.5;
+const STRIPE_GAP: f64 = 0.5;
+/// Crosswalks at the ends of roads are placed this far back from the intersection polygon
+const INTERSECTION_SETBACK: f64 = 1.0;
+/// Crossing nodes this close to the end of a road are at the intersection
+const END_OF_ROAD: f64 = 0.1;
+/// Don't add automatic crosswalks if a mapped crossing is this close to the end of the road
+const MAPPED_CROSSING_RADIUS: f64 = 10.0;
+
+pub struct Crosswalk {
+    pub road: RoadID,
+    /// None for automatic crosswalks
+    pub node: Option<NodeID>,
+    pub kind: Option<String>,
+    pub polygon: Polygon,
+    /// Only for zebra crossings
+    pub stripes: Vec<Polygon>,
+}
+
+/// Generates crosswalks at every mapped crossing, and optionally where roads enter intersections
+/// with more than two roads, not counting footways
+pub fn all_crosswalks(map: &MapModel, auto: bool) -> Vec<Crosswalk> {
+    let mut crosswalks = Vec::new();
+    // A crossing at the end of two roads only gets one crosswalk
+    let mut seen_nodes = HashSet::new();
+    // Crossings at the end of a road are often at the intersection with the footway that crosses,
+    // so look for them on the roads being crossed
+    for road in &map.roads {
+        if is_footway(&road.tags) {
+            continue;
+        }
+        for crossing in road.nodes.iter().filter(|n| n.is(NodeKind::Crossing)) {
+            if !seen_nodes.insert(crossing.node) {
+                continue;
+            }
+            // A crossing on the intersection node would be drawn in the middle of the
+            // intersection, so move it out along the road. If that's not possible, it's still a
+            // real crossing, so keep it where it's mapped.
+            let length = road.linestring.euclidean_length();
+            let at_end = if crossing.dist_along < END_OF_ROAD {
+                Some((road.src_i, true))
+            } else if length - crossing.dist_along < END_OF_ROAD {
+                Some((road.dst_i, false))
+            } else {
+                None
+            };
+            let dist_along = at_end
+                .and_then(|(i, at_start)| {
+                    outside_intersection(road, at_start, &intersection_polygon(map, i)?)
+                })
+                .unwrap_or(crossing.dist_along);
+            crosswalks.extend(crosswalk(
+                road,
+                dist_along,
+                Some(crossing.node),
+                crossing.tags.get("crossing").cloned(),
+            ));
+        }
+    }
+
+    if auto {
+        for i in &map.intersections {
+            if num_carriageways(map, i.id) < 3 {
+                continue;
+            }
+            let Some(polygon) = intersection_polygon(map, i.id) else {
+                continue;
+            };
+            for r in &i.roads {
+                let road = &map.roads[r.0];
+                if is_footway(&road.tags) {
+                    continue;
+                }
+                let Some(dist_along) = outside_intersection(road, road.src_i == i.id, &polygon)
+                else {
+                    continue;
+                };
+                if road.nodes.iter().any(|n| {
+                    n.is(NodeKind::Crossing)
+                        && (n.dist_along - dist_along).abs() < MAPPED_CROSSING_RADIUS
+                }) {
+                    continue;
+                }
+                crosswalks.extend(crosswalk(road, dist_along, None, None));
+            }
+        }
+    }
+
+    crosswalks
+}
+
+/// Where along the road a crosswalk just outside an intersection polygon at one end should be
+/// centered. None if the road doesn't leave the polygon, or is too short.
+fn outside_intersection(road: &Road, at_start: bool, polygon: &MultiPolygon) -> Option<f64> {
+    let length = road.linestring.euclidean_length();
+    let from_end = if at_start {
+        first_crossing(&road.linestring, polygon)
+    } else {
+        let mut reversed = road.linestring.clone();
+        reversed.0.reverse();
+        first_crossing(&reversed, polygon)
+    }?;
+    let from_end = from_end + INTERSECTION_SETBACK + CROSSWALK_LENGTH / 2.0;
+    if from_end >= length {
+        return None;
+    }
+    Some(if at_start {
+        from_end
+    } else {
+        length - from_end
+    })
+}
+
+fn crosswalk(
+    road: &Road,
+    dist_along: f64,
+    node: Option<NodeID>,
+    kind: Option<String>,
+) -> Option<Crosswalk> {
+    // Span the carriageway if it's known, otherwise the whole corridor
+    let (left, right) = match road.cross_section {
+        Some(ref cross_section) => (
+            cross_section.carriageway_left,
+            cross_section.carriageway_right,
+        ),
+        None => (road.max_left_width?, road.max_right_width?),
+    };
+    let (pt, angle) = point_and_angle_at(&road.linestring, dist_along)?;
+    let polygon = rectangle(pt, angle, CROSSWALK_LENGTH, -left, right);
+
+    let zebra = matches!(kind.as_deref(), Some("zebra"));
+    let mut stripes = Vec::new();
+    if zebra {
+        let mut offset = -left;
+        while offset + STRIPE_WIDTH <= right {
+            stripes.push(rectangle(
+                pt,
+                angle,
+                CROSSWALK_LENGTH,
+                offset,
+                offset + STRIPE_WIDTH,
+            ));
+            offset += STRIPE_WIDTH + STRIPE_GAP;
+        }
+    }
+
+    Some(Crosswalk {
+        road: road.id,
+        node,
+        kind,
+        polygon,
+        stripes,
+    })
+}
+
+/// A rectangle centered on `pt` along a road going in the direction of `angle`, `length` long,
+/// between two sideways offsets (negative to the left)
+fn rectangle(pt: Coord, angle: f64, length: f64, offset1: f64, offset2: f64) -> Polygon {
+    let back = project_away(pt, angle + 180.0, length / 2.0);
+    let front = project_away(pt, angle, length / 2.0);
+    // Positive offsets are to the right, which is +90 degrees; see find_road_width
+    let side = |c: Coord, offset: f64| project_away(c, angle + 90.0, offset);
+    Polygon::new(
+        LineString::new(vec![
+            side(back, offset1),
+            side(front, offset1),
+            side(front, offset2),
+            side(back, offset2),
+        ]),
+        Vec::new(),
+    )
+}
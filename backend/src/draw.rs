@@ -0,0 +1,419 @@
+use anyhow::{bail, Result};
+use geo::{Coord, LineString, Point, Polygon, Rect};
+use serde::Deserialize;
+use tsify::Tsify;
+
+use crate::crossings::all_crosswalks;
+use crate::find_road_width::{self, find_road_width};
+use crate::intersection_geometry::{self, intersection_polygon};
+use crate::markings::{all_markings, Marking, MarkingGeometry, Pattern};
+use crate::obstacles::ObstacleGeometry;
+use crate::MapModel;
+
+/// Controls what SVG and PNG output draws. A layer set to null isn't drawn; a missing layer uses
+/// the default style.
+#[derive(Deserialize, Tsify)]
+#[serde(default)]
+pub struct DrawOptions {
+    /// The area to draw, as [x1, y1, x2, y2] in Mercator meters, with x1 < x2 and y1 < y2.
+    /// Defaults to the whole map.
+    #[tsify(optional)]
+    pub viewbox: Option<[f64; 4]>,
+    /// The width of the image in pixels. The height follows the viewbox's aspect ratio.
+    pub width: f64,
+    /// Accepts the same colors as `Style::fill`
+    pub background: Option<String>,
+
+    pub buildings: Option<Style>,
+    pub obstacles: Option<Style>,
+    pub road_polygons: Option<Style>,
+    pub intersection_polygons: Option<Style>,
+    pub centerlines: Option<Style>,
+    /// Dashed markings use the style's dasharray, or 3m dashes. Stop lines are filled with the
+    /// stroke color. Generating lanes for every road is slow, so off by default.
+    pub markings: Option<Style>,
+    /// The outline of every crosswalk uses the stroke, and zebra stripes use the fill. Off by
+    /// default.
+    pub crosswalks: Option<Style>,
+    /// Also draw crosswalks where roads enter intersections with more than two roads
+    pub auto_crosswalks: bool,
+    /// The probes from `find_road_width` for every road. Slow, so off by default.
+    pub test_lines: Option<Style>,
+    pub graph_edges: Option<Style>,
+    pub graph_nodes: Option<Style>,
+}
+
+#[derive(Clone, Deserialize, Tsify)]
+#[serde(default)]
+pub struct Style {
+    /// Any CSS color for SVG. PNG output only understands #rgb, #rrggbb and the names black,
+    /// white, grey, red, green, blue, yellow, orange, purple, cyan, magenta, brown and pink, and
+    /// draws anything else in black.
+    pub fill: Option<String>,
+    /// Accepts the same colors as `fill`
+    pub stroke: Option<String>,
+    /// In meters
+    pub stroke_width: f64,
+    /// For points, in meters
+    pub radius: f64,
+    pub opacity: f64,
+    /// Alternating dash and gap lengths, in meters
+    #[tsify(optional)]
+    pub dasharray: Option<Vec<f64>>,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            fill: None,
+            stroke: Some("black".to_string()),
+            stroke_width: 1.0,
+            radius: 1.0,
+            opacity: 1.0,
+            dasharray: None,
+        }
+    }
+}
+
+impl Style {
+    pub fn new(fill: Option<&str>, stroke: Option<&str>, stroke_width: f64) -> Self {
+        Self {
+            fill: fill.map(|x| x.to_string()),
+            stroke: stroke.map(|x| x.to_string()),
+            stroke_width,
+            ..Default::default()
+        }
+    }
+
+    fn opacity(mut self, opacity: f64) -> Self {
+        self.opacity = opacity;
+        self
+    }
+
+    fn dashed(mut self, dasharray: Vec<f64>) -> Self {
+        self.dasharray = Some(dasharray);
+        self
+    }
+}
+
+// Matches the colors of the web app
+impl Default for DrawOptions {
+    fn default() -> Self {
+        Self {
+            viewbox: None,
+            width: 1000.0,
+            background: Some("grey".to_string()),
+
+            buildings: Some(Style::new(Some("red"), Some("black"), 0.5)),
+            obstacles: Some(Style::new(None, Some("brown"), 0.5)),
+            road_polygons: Some(Style::new(None, Some("green"), 0.5)),
+            intersection_polygons: Some(Style::new(Some("purple"), None, 0.0)),
+            centerlines: Some(Style::new(None, Some("black"), 1.0)),
+            markings: None,
+            crosswalks: None,
+            auto_crosswalks: false,
+            test_lines: None,
+            graph_edges: None,
+            graph_nodes: None,
+        }
+    }
+}
+
+/// Something that can draw styled geometry in Mercator coordinates, like an SVG or PNG writer.
+/// Every shape belongs to the most recently started layer.
+pub trait Painter {
+    fn begin_layer(&mut self, id: &str, style: &Style);
+    fn end_layer(&mut self);
+
+    fn polygon(&mut self, polygon: &Polygon);
+    fn linestring(&mut self, linestring: &LineString);
+    fn point(&mut self, pt: Point);
+}
+
+pub fn map_viewbox(map: &MapModel, options: &DrawOptions) -> Result<Rect> {
+    let [x1, y1, x2, y2] =
+        options
+            .viewbox
+            .unwrap_or([0.0, 0.0, map.mercator.width, map.mercator.height]);
+    // Rect::new would quietly swap the corners
+    if !(x1 < x2 && y1 < y2) {
+        bail!("The viewbox [{x1}, {y1}, {x2}, {y2}] is empty or inverted");
+    }
+    Ok(Rect::new(Coord { x: x1, y: y1 }, Coord { x: x2, y: y2 }))
+}
+
+/// Painters scale by the viewbox, so it needs some area
+pub fn check_viewbox(viewbox: Rect) -> Result<()> {
+    if !(viewbox.width() > 0.0 && viewbox.height() > 0.0) {
+        bail!("Can't draw the empty viewbox {:?}", viewbox);
+    }
+    Ok(())
+}
+
+pub fn draw_map(map: &MapModel, options: &DrawOptions, painter: &mut dyn Painter) {
+    if let Some(ref style) = options.buildings {
+        painter.begin_layer("buildings", style);
+        for b in &map.buildings {
+            painter.polygon(&b.polygon);
+        }
+        painter.end_layer();
+    }
+    if let Some(ref style) = options.obstacles {
+        painter.begin_layer("obstacles", style);
+        for o in &map.obstacles {
+            match o.geometry {
+                ObstacleGeometry::Polygon(ref polygon) => painter.polygon(polygon),
+                ObstacleGeometry::LineString(ref linestring) => painter.linestring(linestring),
+            }
+        }
+        painter.end_layer();
+    }
+    if let Some(ref style) = options.road_polygons {
+        painter.begin_layer("road_polygons", style);
+        for r in &map.roads {
+            if let Some(ref polygon) = r.polygon {
+                painter.polygon(polygon);
+            }
+        }
+        painter.end_layer();
+    }
+    if let Some(ref style) = options.intersection_polygons {
+        painter.begin_layer("intersection_polygons", style);
+        for i in &map.intersections {
+            if let Some(multipolygon) = intersection_polygon(map, i.id) {
+                for polygon in &multipolygon {
+                    painter.polygon(polygon);
+                }
+            }
+        }
+        painter.end_layer();
+    }
+    if let Some(ref style) = options.centerlines {
+        painter.begin_layer("centerlines", style);
+        for r in &map.roads {
+            painter.linestring(&r.linestring);
+        }
+        painter.end_layer();
+    }
+    if let Some(ref style) = options.markings {
+        draw_markings(&all_markings(map), style, painter);
+    }
+    if let Some(ref style) = options.crosswalks {
+        let crosswalks = all_crosswalks(map, options.auto_crosswalks);
+        painter.begin_layer(
+            "crosswalks",
+            &Style {
+                fill: None,
+                ..style.clone()
+            },
+        );
+        for crosswalk in &crosswalks {
+            painter.polygon(&crosswalk.polygon);
+        }
+        painter.end_layer();
+
+        painter.begin_layer(
+            "zebra_stripes",
+            &Style {
+                stroke: None,
+                ..style.clone()
+            },
+        );
+        for polygon in crosswalks.iter().flat_map(|c| &c.stripes) {
+            painter.polygon(polygon);
+        }
+        painter.end_layer();
+    }
+    if let Some(ref style) = options.test_lines {
+        painter.begin_layer("test_lines", style);
+        for r in &map.roads {
+            for test in find_road_width(map, r.id).test_lines {
+                // Only draw up to whatever the probe hit
+                let line = test.hit.map(|(line, _)| line).unwrap_or(test.full_line);
+                painter.linestring(&LineString::new(vec![line.start, line.end]));
+            }
+        }
+        painter.end_layer();
+    }
+    if let Some(ref style) = options.graph_edges {
+        painter.begin_layer("graph_edges", style);
+        for linestring in map.graph.edge_linestrings() {
+            painter.linestring(linestring);
+        }
+        painter.end_layer();
+    }
+    if let Some(ref style) = options.graph_nodes {
+        painter.begin_layer("graph_nodes", style);
+        for pt in map.graph.node_points() {
+            painter.point(pt);
+        }
+        painter.end_layer();
+    }
+}
+
+fn draw_markings(markings: &[Marking], style: &Style, painter: &mut dyn Painter) {
+    let dashed_style = if style.dasharray.is_some() {
+        style.clone()
+    } else {
+        style.clone().dashed(vec![3.0, 3.0])
+    };
+    let stop_line_style = Style {
+        fill: style.stroke.clone(),
+        stroke: None,
+        dasharray: None,
+        ..style.clone()
+    };
+
+    for (id, pattern, style) in [
+        ("markings", Pattern::Solid, style),
+        ("dashed_markings", Pattern::Dashed, &dashed_style),
+    ] {
+        painter.begin_layer(id, style);
+        for m in markings.iter().filter(|m| m.pattern == pattern) {
+            if let MarkingGeometry::LineString(ref linestring) = m.geometry {
+                painter.linestring(linestring);
+            }
+        }
+        painter.end_layer();
+    }
+
+    painter.begin_layer("stop_lines", &stop_line_style);
+    for m in markings {
+        if let MarkingGeometry::Polygon(ref polygon) = m.geometry {
+            painter.polygon(polygon);
+        }
+    }
+    painter.end_layer();
+}
+
+/// Draws the same debug view as the web app's FindRoadWidth mode
+pub fn draw_road_width(out: &find_road_width::Output, painter: &mut dyn Painter) {
+    for (id, left, color) in [
+        ("left_test_lines", true, "red"),
+        ("right_test_lines", false, "blue"),
+    ] {
+        painter.begin_layer(id, &Style::new(None, Some(color), 0.1));
+        for test in out
+            .test_lines
+            .iter()
+            .filter(|t| t.left == left && t.hit.is_none())
+        {
+            painter.linestring(&LineString::new(vec![
+                test.full_line.start,
+                test.full_line.end,
+            ]));
+        }
+        painter.end_layer();
+
+        painter.begin_layer(
+            &format!("{id}_hits"),
+            &Style::new(None, Some(color), 0.1).dashed(vec![0.5, 0.5]),
+        );
+        for test in out.test_lines.iter().filter(|t| t.left == left) {
+            if let Some((line, _)) = test.hit {
+                painter.linestring(&LineString::new(vec![line.start, line.end]));
+            }
+        }
+        painter.end_layer();
+    }
+
+    painter.begin_layer("buffered_polygon", &Style::new(None, Some("green"), 0.5));
+    if let Some(ref polygon) = out.buffered_polygon {
+        painter.polygon(polygon);
+    }
+    painter.end_layer();
+
+    painter.begin_layer(
+        "parallel_roads",
+        &Style::new(None, Some("orange"), 2.0).opacity(0.5),
+    );
+    for (linestring, _) in &out.parallel_roads {
+        painter.linestring(linestring);
+    }
+    painter.end_layer();
+}
+
+/// Draws the same debug view as the web app's IntersectionGeometry mode
+pub fn draw_intersection_geometry(out: &intersection_geometry::Output, painter: &mut dyn Painter) {
+    painter.begin_layer(
+        "thick_roads",
+        &Style::new(Some("green"), None, 0.0).opacity(0.5),
+    );
+    for polygon in &out.thick_roads {
+        painter.polygon(polygon);
+    }
+    painter.end_layer();
+
+    painter.begin_layer("overlaps", &Style::new(Some("red"), None, 0.0).opacity(0.5));
+    for polygon in out.overlaps.iter().flatten() {
+        painter.polygon(polygon);
+    }
+    painter.end_layer();
+
+    painter.begin_layer("unioned", &Style::new(Some("blue"), None, 0.0).opacity(0.5));
+    for polygon in &out.unioned {
+        painter.polygon(polygon);
+    }
+    painter.end_layer();
+}
+
+/// Doesn't draw anything, just finds the bounds of everything drawn. Use this to frame debug
+/// views.
+#[derive(Default)]
+pub struct BoundsPainter {
+    bounds: Option<Rect>,
+}
+
+impl BoundsPainter {
+    /// The bounds of everything drawn, expanded by `margin` meters on each side
+    pub fn bounds(&self, margin: f64) -> Option<Rect> {
+        let rect = self.bounds?;
+        Some(Rect::new(
+            Coord {
+                x: rect.min().x - margin,
+                y: rect.min().y - margin,
+            },
+            Coord {
+                x: rect.max().x + margin,
+                y: rect.max().y + margin,
+            },
+        ))
+    }
+
+    fn add(&mut self, c: Coord) {
+        self.bounds = Some(match self.bounds {
+            Some(rect) => Rect::new(
+                Coord {
+                    x: rect.min().x.min(c.x),
+                    y: rect.min().y.min(c.y),
+                },
+                Coord {
+                    x: rect.max().x.max(c.x),
+                    y: rect.max().y.max(c.y),
+                },
+            ),
+            None => Rect::new(c, c),
+        });
+    }
+}
+
+impl Painter for BoundsPainter {
+    fn begin_layer(&mut self, _: &str, _: &Style) {}
+    fn end_layer(&mut self) {}
+
+    fn polygon(&mut self, polygon: &Polygon) {
+        for c in polygon.exterior().coords() {
+            self.add(*c);
+        }
+    }
+
+    fn linestring(&mut self, linestring: &LineString) {
+        for c in linestring.coords() {
+            self.add(*c);
+        }
+    }
+
+    fn point(&mut self, pt: Point) {
+        self.add(pt.into());
+    }
+}
@@ -0,0 +1,163 @@
+use anyhow::{bail, Result};
+use flatgeobuf::{ColumnType, ColumnValue, FgbCrs, FgbWriter, FgbWriterOptions, GeometryType};
+
+use crate::intersection_geometry::intersection_polygon;
+use crate::output::tags_to_map;
+use crate::MapModel;
+
+/// The layers that can be exported as FlatGeobuf, one file each
+#[derive(Clone, Copy)]
+pub enum Layer {
+    Centerlines,
+    RoadPolygons,
+    IntersectionPolygons,
+    Buildings,
+}
+
+impl Layer {
+    pub const ALL: [Layer; 4] = [
+        Layer::Centerlines,
+        Layer::RoadPolygons,
+        Layer::IntersectionPolygons,
+        Layer::Buildings,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layer::Centerlines => "centerlines",
+            Layer::RoadPolygons => "road_polygons",
+            Layer::IntersectionPolygons => "intersection_polygons",
+            Layer::Buildings => "buildings",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self> {
+        match Layer::ALL.into_iter().find(|layer| layer.name() == name) {
+            Some(layer) => Ok(layer),
+            None => bail!("Unknown layer {name}"),
+        }
+    }
+}
+
+/// Writes one layer as FlatGeobuf in WGS84, with a spatial index. Attributes match the
+/// properties in render output; tags are stored as a JSON column.
+pub fn export_layer(map: &MapModel, layer: Layer) -> Result<Vec<u8>> {
+    let geometry_type = match layer {
+        Layer::Centerlines => GeometryType::LineString,
+        Layer::RoadPolygons | Layer::Buildings => GeometryType::Polygon,
+        Layer::IntersectionPolygons => GeometryType::MultiPolygon,
+    };
+    let mut fgb = FgbWriter::create_with_options(
+        layer.name(),
+        geometry_type,
+        FgbWriterOptions {
+            write_index: true,
+            crs: FgbCrs {
+                code: 4326,
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+    )?;
+
+    match layer {
+        Layer::Centerlines => {
+            add_column(&mut fgb, "id", ColumnType::ULong, false);
+            add_column(&mut fgb, "way", ColumnType::Long, false);
+            add_column(&mut fgb, "node1", ColumnType::Long, false);
+            add_column(&mut fgb, "node2", ColumnType::Long, false);
+            add_column(&mut fgb, "component", ColumnType::ULong, false);
+            add_column(&mut fgb, "max_left_width", ColumnType::Double, true);
+            add_column(&mut fgb, "max_right_width", ColumnType::Double, true);
+            add_column(&mut fgb, "tags", ColumnType::Json, false);
+
+            for r in &map.roads {
+                let tags = serde_json::to_string(&tags_to_map(&r.tags))?;
+                fgb.add_feature_geom(to_wgs84(map, r.linestring.clone().into()), |f| {
+                    f.property(0, "id", &ColumnValue::ULong(r.id.0 as u64))
+                        .unwrap();
+                    f.property(1, "way", &ColumnValue::Long(r.way.0)).unwrap();
+                    f.property(2, "node1", &ColumnValue::Long(r.node1.0))
+                        .unwrap();
+                    f.property(3, "node2", &ColumnValue::Long(r.node2.0))
+                        .unwrap();
+                    f.property(4, "component", &ColumnValue::ULong(r.component as u64))
+                        .unwrap();
+                    if let Some(width) = r.max_left_width {
+                        f.property(5, "max_left_width", &ColumnValue::Double(width))
+                            .unwrap();
+                    }
+                    if let Some(width) = r.max_right_width {
+                        f.property(6, "max_right_width", &ColumnValue::Double(width))
+                            .unwrap();
+                    }
+                    f.property(7, "tags", &ColumnValue::Json(&tags)).unwrap();
+                })?;
+            }
+        }
+        Layer::RoadPolygons => {
+            add_column(&mut fgb, "road", ColumnType::ULong, false);
+            add_column(&mut fgb, "max_left_width", ColumnType::Double, false);
+            add_column(&mut fgb, "max_right_width", ColumnType::Double, false);
+
+            for r in &map.roads {
+                let (Some(polygon), Some(left), Some(right)) =
+                    (&r.polygon, r.max_left_width, r.max_right_width)
+                else {
+                    continue;
+                };
+                fgb.add_feature_geom(to_wgs84(map, polygon.clone().into()), |f| {
+                    f.property(0, "road", &ColumnValue::ULong(r.id.0 as u64))
+                        .unwrap();
+                    f.property(1, "max_left_width", &ColumnValue::Double(left))
+                        .unwrap();
+                    f.property(2, "max_right_width", &ColumnValue::Double(right))
+                        .unwrap();
+                })?;
+            }
+        }
+        Layer::IntersectionPolygons => {
+            add_column(&mut fgb, "intersection", ColumnType::ULong, false);
+            add_column(&mut fgb, "node", ColumnType::Long, false);
+
+            for i in &map.intersections {
+                let Some(polygon) = intersection_polygon(map, i.id) else {
+                    continue;
+                };
+                fgb.add_feature_geom(to_wgs84(map, polygon.into()), |f| {
+                    f.property(0, "intersection", &ColumnValue::ULong(i.id.0 as u64))
+                        .unwrap();
+                    f.property(1, "node", &ColumnValue::Long(i.node.0)).unwrap();
+                })?;
+            }
+        }
+        Layer::Buildings => {
+            add_column(&mut fgb, "id", ColumnType::String, false);
+            add_column(&mut fgb, "tags", ColumnType::Json, false);
+
+            for b in &map.buildings {
+                let id = b.id.to_string();
+                let tags = serde_json::to_string(&tags_to_map(&b.tags))?;
+                fgb.add_feature_geom(to_wgs84(map, b.polygon.clone().into()), |f| {
+                    f.property(0, "id", &ColumnValue::String(&id)).unwrap();
+                    f.property(1, "tags", &ColumnValue::Json(&tags)).unwrap();
+                })?;
+            }
+        }
+    }
+
+    let mut out = Vec::new();
+    fgb.write(&mut out)?;
+    Ok(out)
+}
+
+fn add_column(fgb: &mut FgbWriter, name: &str, column_type: ColumnType, nullable: bool) {
+    fgb.add_column(name, column_type, |_, col| {
+        col.nullable = nullable;
+    });
+}
+
+fn to_wgs84(map: &MapModel, mut geom: geo::Geometry) -> geo::Geometry {
+    map.mercator.to_wgs84_in_place(&mut geom);
+    geom
+}
@@ -1,25 +1,48 @@
-use geo::{Densify, EuclideanLength, Intersects, Line, LineString, Polygon};
-use serde::Serialize;
+use geo::{Coord, Densify, EuclideanLength, Intersects, Line, LineString, Polygon};
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
 
-use crate::math::{buffer_linestring, project_away, split_line_by_polygon};
+use crate::math::{buffer_linestring, project_away};
+use crate::obstacles::{cast_ray, ObstacleKind};
 use crate::{MapModel, Road, RoadID};
 
-#[derive(Serialize)]
+#[derive(Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    /// Also stop probes at neighbouring road centerlines, so the polygons of adjacent roads don't
+    /// overlap. Footways, cycleways and service roads are assumed to be narrower than other roads.
+    pub stop_at_roads: bool,
+    /// Don't probe within this many meters of either end of the road, where the space of other
+    /// roads at the intersection interferes. Short roads are probed everywhere regardless.
+    pub skip_near_intersections: f64,
+}
+
+#[derive(Serialize, Tsify)]
+#[serde(rename = "RoadWidthOutput")]
 pub struct Output {
-    test_lines: Vec<TestLine>,
+    pub test_lines: Vec<TestLine>,
     max_left_width: f64,
     max_right_width: f64,
-    buffered_polygon: Option<Polygon>,
-    parallel_roads: Vec<(LineString, String)>,
+    /// What kind of obstacle limited each side, or nothing if the default width was used
+    left_limited_by: Option<ObstacleKind>,
+    right_limited_by: Option<ObstacleKind>,
+    pub buffered_polygon: Option<Polygon>,
+    pub parallel_roads: Vec<(LineString, String)>,
+    /// Some probes started inside a building or obstacle, so the road passes under or through
+    /// something. Those obstacles were ignored.
+    passes_through: bool,
 }
 
-#[derive(Serialize)]
+#[derive(Serialize, Tsify)]
 pub struct TestLine {
     // Right if false
-    left: bool,
-    full_line: Line,
+    pub left: bool,
+    pub full_line: Line,
     // If the line hits something, what's the shortened line and its length?
-    hit: Option<(Line, f64)>,
+    pub hit: Option<(Line, f64)>,
+    pub hit_kind: Option<ObstacleKind>,
+    // The probe started inside an obstacle, which was ignored
+    pub starts_inside: bool,
 }
 
 pub fn find_road_width(map: &MapModel, r: RoadID) -> Output {
@@ -31,48 +54,69 @@ pub fn find_road_width(map: &MapModel, r: RoadID) -> Output {
     let original_road = &map.roads[r.0];
     let dense_line = original_road.linestring.densify(step_size_meters);
 
-    let mut test_lines = Vec::new();
-    // Using lines instead of coords so we can get the angle -- but is this hard to reason about?
-    // angle_at_point instead?
-    for orig_line in dense_line.lines() {
-        // TODO For the last line, use the last point too
-        let pt = orig_line.start;
-        let angle = line_angle_degrees(orig_line);
+    let total_length = dense_line.euclidean_length();
+    let skip = map.road_width_options.skip_near_intersections;
+    let skip = if total_length > 2.0 * skip { skip } else { 0.0 };
+
+    let vertices = vertex_angles(&dense_line);
+    let mut probes: Vec<(Coord, f64, f64)> = vertices
+        .iter()
+        .filter(|(_, _, dist_along)| *dist_along >= skip && total_length - *dist_along >= skip)
+        .cloned()
+        .collect();
+    // A road just over twice the skip distance might have no vertex left in between, so keep the
+    // one closest to the middle
+    if probes.is_empty() {
+        let middle = |dist_along: f64| (dist_along - total_length / 2.0).abs();
+        probes.extend(
+            vertices
+                .into_iter()
+                .min_by(|a, b| middle(a.2).partial_cmp(&middle(b.2)).unwrap()),
+        );
+    }
 
+    let mut test_lines = Vec::new();
+    for (pt, angle, _) in probes {
         for (angle_offset, left) in [(-90.0, true), (90.0, false)] {
             let projected = project_away(pt, angle + angle_offset, project_away_meters);
             let full_line = Line::new(pt, projected);
 
-            let mut hit = None;
-            for b in &map.buildings {
-                if b.polygon.intersects(&full_line) {
-                    if let Some(shortened_line) = split_line_by_polygon(full_line, &b.polygon) {
-                        hit = Some((shortened_line, shortened_line.euclidean_length()));
-                        break;
-                    }
-                }
-            }
+            let cast = cast_ray(map, original_road, full_line);
+            let (hit, hit_kind) = match cast.hit {
+                Some((line, length, kind)) => (Some((line, length)), Some(kind)),
+                None => (None, None),
+            };
 
             test_lines.push(TestLine {
                 left,
                 full_line,
                 hit,
+                hit_kind,
+                starts_inside: cast.starts_inside,
             });
         }
     }
 
     let mut max_right_width = project_away_meters;
     let mut max_left_width = project_away_meters;
+    let mut left_limited_by = None;
+    let mut right_limited_by = None;
     for test in &test_lines {
         if let Some((_, len)) = test.hit {
             if test.left {
-                max_left_width = max_left_width.min(len);
-            } else {
-                max_right_width = max_right_width.min(len);
+                if len < max_left_width {
+                    max_left_width = len;
+                    left_limited_by = test.hit_kind;
+                }
+            } else if len < max_right_width {
+                max_right_width = len;
+                right_limited_by = test.hit_kind;
             }
         }
     }
 
+    let passes_through = test_lines.iter().any(|test| test.starts_inside);
+
     let buffered_polygon =
         buffer_linestring(&original_road.linestring, max_left_width, max_right_width);
     let parallel_roads = if let Some(ref poly) = buffered_polygon {
@@ -85,8 +129,11 @@ pub fn find_road_width(map: &MapModel, r: RoadID) -> Output {
         test_lines,
         max_left_width,
         max_right_width,
+        left_limited_by,
+        right_limited_by,
         buffered_polygon,
         parallel_roads,
+        passes_through,
     }
 }
 
@@ -99,6 +146,8 @@ pub fn find_all(map: &mut MapModel) {
     for (road, out) in map.roads.iter_mut().zip(results.into_iter()) {
         road.max_left_width = Some(out.max_left_width);
         road.max_right_width = Some(out.max_right_width);
+        road.left_limited_by = out.left_limited_by;
+        road.right_limited_by = out.right_limited_by;
         road.polygon = buffer_linestring(&road.linestring, out.max_left_width, out.max_right_width);
     }
 }
@@ -131,6 +180,48 @@ fn find_parallel_roads(
     parallel
 }
 
+/// For every vertex, returns the point, the direction of the line there in degrees, and the
+/// distance along the line. At interior vertices, the direction bisects the two adjacent segments,
+/// so probes perpendicular to it don't point into the road's own corner at sharp bends.
+fn vertex_angles(linestring: &LineString) -> Vec<(Coord, f64, f64)> {
+    let lines: Vec<Line> = linestring
+        .lines()
+        .filter(|line| line.euclidean_length() > 0.0)
+        .collect();
+    let Some(last) = lines.last() else {
+        return Vec::new();
+    };
+
+    let mut result = Vec::new();
+    let mut dist_along = 0.0;
+    for (idx, line) in lines.iter().enumerate() {
+        let angle = if idx == 0 {
+            line_angle_degrees(*line)
+        } else {
+            bisect_angle(lines[idx - 1], *line)
+        };
+        result.push((line.start, angle, dist_along));
+        dist_along += line.euclidean_length();
+    }
+    result.push((last.end, line_angle_degrees(*last), dist_along));
+    result
+}
+
+fn bisect_angle(incoming: Line, outgoing: Line) -> f64 {
+    let unit = |line: Line| {
+        let len = line.euclidean_length();
+        (line.dx() / len, line.dy() / len)
+    };
+    let (x1, y1) = unit(incoming);
+    let (x2, y2) = unit(outgoing);
+    let (x, y) = (x1 + x2, y1 + y2);
+    // The line doubles back on itself, so there's no sensible bisector
+    if x.abs() < 1e-9 && y.abs() < 1e-9 {
+        return line_angle_degrees(incoming);
+    }
+    y.atan2(x).to_degrees()
+}
+
 // TODO move to math
 
 fn line_angle_degrees(line: Line) -> f64 {
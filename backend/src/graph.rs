@@ -1,12 +1,24 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 
-use geo::{LineString, Point};
-use serde::Serialize;
+use anyhow::{anyhow, bail, Result};
+use geo::{EuclideanDistance, EuclideanLength, LineString, Point};
+use geojson::{Feature, GeoJson, Geometry};
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
 
-use crate::{Intersection, IntersectionID, Road, RoadID};
+use crate::components::label_components;
+use crate::math::{quadratic_bezier, slice_linestring};
+use crate::{route, Intersection, IntersectionID, MapModel, Road, RoadID};
+
+/// When an edge is reattached to a merged node, how much of the original geometry to cut away
+/// before curving towards the new node
+const TRIM_METERS: f64 = 10.0;
+const CURVE_STEPS: usize = 8;
+/// How far an edge's geometry may end from its node's point
+const ENDPOINT_EPSILON: f64 = 0.01;
 
 /// Much more mutable than a MapModel, but refers back to original roads and intersections.
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Tsify)]
 pub struct Graph {
     edges: HashMap<EdgeID, Edge>,
     nodes: HashMap<NodeID, Node>,
@@ -14,12 +26,40 @@ pub struct Graph {
     next_node_id: usize,
 }
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct EdgeID(pub usize);
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct NodeID(pub usize);
 
-#[derive(Clone, Serialize)]
+/// Everything needed to restore a graph, including the original geometry that `render` leaves out
+#[derive(Serialize, Deserialize)]
+pub struct GraphSnapshot {
+    edges: Vec<EdgeSnapshot>,
+    nodes: Vec<NodeSnapshot>,
+    next_edge_id: usize,
+    next_node_id: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct EdgeSnapshot {
+    id: EdgeID,
+    node1: NodeID,
+    node2: NodeID,
+    linestring: LineString,
+    original_linestring: LineString,
+    roads: Vec<RoadID>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct NodeSnapshot {
+    id: NodeID,
+    edges: Vec<EdgeID>,
+    point: Point,
+    intersections: Vec<IntersectionID>,
+}
+
+#[derive(Clone, Serialize, Tsify)]
+#[serde(rename = "GraphEdge")]
 struct Edge {
     id: EdgeID,
     node1: NodeID,
@@ -27,21 +67,62 @@ struct Edge {
 
     linestring: LineString,
     roads: HashSet<RoadID>,
+
+    // Never modified, so repeated merges can reshape the ends from scratch
+    #[serde(skip)]
+    original_linestring: LineString,
 }
 
 impl Edge {
-    fn other_node(&self, n: NodeID) -> NodeID {
+    fn other_node(&self, n: NodeID) -> Result<NodeID> {
         // TODO Loops
         if self.node1 == n {
-            self.node2
+            Ok(self.node2)
+        } else if self.node2 == n {
+            Ok(self.node1)
         } else {
-            assert_eq!(self.node2, n);
-            self.node1
+            bail!("{:?} isn't attached to {:?}", self.id, n)
         }
     }
+
+    /// Reshapes the ends of the original geometry to meet `pt1` and `pt2`, the current positions
+    /// of `node1` and `node2`. An end that has moved is trimmed back by `trim_meters` and joined
+    /// to the new point with a curve bending through the original endpoint.
+    fn reshape_ends(&mut self, pt1: Point, pt2: Point, trim_meters: f64) {
+        let original = &self.original_linestring;
+        let orig_start = original.0[0];
+        let orig_end = *original.0.last().unwrap();
+        let moved_start = Point::from(orig_start).euclidean_distance(&pt1) > 1e-6;
+        let moved_end = Point::from(orig_end).euclidean_distance(&pt2) > 1e-6;
+
+        // Don't trim away the whole edge
+        let length = original.euclidean_length();
+        let trim = trim_meters.min(length / 3.0);
+        let steps = if trim > 0.0 { CURVE_STEPS } else { 1 };
+        let middle = slice_linestring(
+            original,
+            if moved_start { trim } else { 0.0 },
+            length - if moved_end { trim } else { 0.0 },
+        )
+        .unwrap_or_else(|| original.clone());
+
+        let mut pts = Vec::new();
+        if moved_start {
+            pts.extend(quadratic_bezier(pt1.into(), orig_start, middle.0[0], steps));
+            // The middle starts with this point
+            pts.pop();
+        }
+        pts.extend(middle.0);
+        if moved_end {
+            let last = pts.pop().unwrap();
+            pts.extend(quadratic_bezier(last, orig_end, pt2.into(), steps));
+        }
+        self.linestring = LineString::new(pts);
+    }
 }
 
-#[derive(Clone, Serialize)]
+#[derive(Clone, Serialize, Tsify)]
+#[serde(rename = "GraphNode")]
 struct Node {
     id: NodeID,
     edges: HashSet<EdgeID>,
@@ -89,6 +170,7 @@ impl Graph {
 
                     linestring: r.linestring.clone(),
                     roads: HashSet::from([r.id]),
+                    original_linestring: r.linestring.clone(),
                 },
             );
             graph.nodes.get_mut(&node1).unwrap().edges.insert(id);
@@ -98,6 +180,90 @@ impl Graph {
         graph
     }
 
+    /// The current geometry of every edge, ordered by ID
+    pub fn edge_linestrings(&self) -> Vec<&LineString> {
+        sorted_values(&self.edges)
+            .into_iter()
+            .map(|e| &e.linestring)
+            .collect()
+    }
+
+    /// The position of every node, ordered by ID
+    pub fn node_points(&self) -> Vec<Point> {
+        sorted_values(&self.nodes)
+            .into_iter()
+            .map(|n| n.point)
+            .collect()
+    }
+
+    pub fn to_snapshot(&self) -> GraphSnapshot {
+        GraphSnapshot {
+            edges: sorted_values(&self.edges)
+                .into_iter()
+                .map(|e| EdgeSnapshot {
+                    id: e.id,
+                    node1: e.node1,
+                    node2: e.node2,
+                    linestring: e.linestring.clone(),
+                    original_linestring: e.original_linestring.clone(),
+                    roads: sorted(e.roads.iter().cloned()),
+                })
+                .collect(),
+            nodes: sorted_values(&self.nodes)
+                .into_iter()
+                .map(|n| NodeSnapshot {
+                    id: n.id,
+                    edges: sorted(n.edges.iter().cloned()),
+                    point: n.point,
+                    intersections: sorted(n.intersections.iter().cloned()),
+                })
+                .collect(),
+            next_edge_id: self.next_edge_id,
+            next_node_id: self.next_node_id,
+        }
+    }
+
+    pub fn from_snapshot(snapshot: GraphSnapshot) -> Result<Self> {
+        let graph = Graph {
+            edges: snapshot
+                .edges
+                .into_iter()
+                .map(|e| {
+                    (
+                        e.id,
+                        Edge {
+                            id: e.id,
+                            node1: e.node1,
+                            node2: e.node2,
+                            linestring: e.linestring,
+                            roads: e.roads.into_iter().collect(),
+                            original_linestring: e.original_linestring,
+                        },
+                    )
+                })
+                .collect(),
+            nodes: snapshot
+                .nodes
+                .into_iter()
+                .map(|n| {
+                    (
+                        n.id,
+                        Node {
+                            id: n.id,
+                            edges: n.edges.into_iter().collect(),
+                            point: n.point,
+                            intersections: n.intersections.into_iter().collect(),
+                        },
+                    )
+                })
+                .collect(),
+            next_edge_id: snapshot.next_edge_id,
+            next_node_id: snapshot.next_node_id,
+        };
+        graph.validate()?;
+        Ok(graph)
+    }
+
     fn new_edge_id(&mut self) -> EdgeID {
         let x = EdgeID(self.next_edge_id);
         self.next_edge_id += 1;
@@ -111,64 +277,232 @@ impl Graph {
     }
 
     pub fn render(&self) -> String {
-        serde_json::to_string(self).unwrap()
+        serde_json::to_string(&self.render_output()).unwrap()
+    }
+
+    pub fn render_output(&self) -> RenderedGraph<'_> {
+        let (node_components, edge_components) = self.components();
+        RenderedGraph {
+            graph: self,
+            node_components,
+            edge_components,
+        }
+    }
+
+    /// Labels every node and edge with a connected component. Components are numbered by
+    /// decreasing number of nodes, so the largest is always 0.
+    pub fn components(&self) -> (BTreeMap<NodeID, usize>, BTreeMap<EdgeID, usize>) {
+        let node_ids: Vec<NodeID> = sorted(self.nodes.keys().cloned());
+        let node_to_idx: HashMap<NodeID, usize> = node_ids
+            .iter()
+            .enumerate()
+            .map(|(idx, n)| (*n, idx))
+            .collect();
+        let labels = label_components(
+            node_ids.len(),
+            self.edges
+                .values()
+                .map(|e| (node_to_idx[&e.node1], node_to_idx[&e.node2])),
+        );
+
+        let node_components = node_ids.into_iter().zip(labels.iter().cloned()).collect();
+        let edge_components = self
+            .edges
+            .values()
+            .map(|e| (e.id, labels[node_to_idx[&e.node1]]))
+            .collect();
+        (node_components, edge_components)
+    }
+
+    /// Exports the current state of the graph as GeoJSON in WGS84, with one feature per edge and
+    /// node. Edges carry the source roads, their OSM ways, and their merged tags under `tags`.
+    pub fn to_geojson(&self, map: &MapModel) -> GeoJson {
+        GeoJson::from(self.to_features(map, true))
+    }
+
+    /// The features of `to_geojson`, in WGS84 or Mercator
+    pub fn to_features(&self, map: &MapModel, wgs84: bool) -> Vec<Feature> {
+        let (node_components, edge_components) = self.components();
+        let mut features = Vec::new();
+
+        for edge in sorted_values(&self.edges) {
+            let mut linestring = edge.linestring.clone();
+            if wgs84 {
+                map.mercator.to_wgs84_in_place(&mut linestring);
+            }
+            let mut f = Feature::from(Geometry::from(&linestring));
+            f.set_property("edge", edge.id.0);
+            f.set_property("node1", edge.node1.0);
+            f.set_property("node2", edge.node2.0);
+            f.set_property("component", edge_components[&edge.id]);
+            f.set_property("length", edge.linestring.euclidean_length());
+
+            let roads: BTreeSet<RoadID> = edge.roads.iter().cloned().collect();
+            f.set_property("roads", roads.iter().map(|r| r.0).collect::<Vec<_>>());
+            f.set_property(
+                "ways",
+                roads
+                    .iter()
+                    .map(|r| map.roads[r.0].way.to_string())
+                    .collect::<BTreeSet<_>>()
+                    .into_iter()
+                    .collect::<Vec<_>>(),
+            );
+            // Nested, so tags like `length` don't clobber the properties above
+            f.set_property(
+                "tags",
+                serde_json::to_value(merge_tags(roads.iter().map(|r| &map.roads[r.0]))).unwrap(),
+            );
+            f.set_property("kind", "graph_edge");
+            features.push(f);
+        }
+
+        for node in sorted_values(&self.nodes) {
+            let mut point = node.point;
+            if wgs84 {
+                map.mercator.to_wgs84_in_place(&mut point);
+            }
+            let mut f = Feature::from(Geometry::from(&point));
+            f.set_property("node", node.id.0);
+            f.set_property("component", node_components[&node.id]);
+            f.set_property(
+                "edges",
+                sorted(node.edges.iter().cloned())
+                    .into_iter()
+                    .map(|e| e.0)
+                    .collect::<Vec<_>>(),
+            );
+            let intersections = sorted(node.intersections.iter().cloned());
+            f.set_property(
+                "osm_nodes",
+                intersections
+                    .iter()
+                    .map(|i| map.intersections[i.0].node.to_string())
+                    .collect::<Vec<_>>(),
+            );
+            f.set_property(
+                "intersections",
+                intersections.into_iter().map(|i| i.0).collect::<Vec<_>>(),
+            );
+            f.set_property("kind", "graph_node");
+            features.push(f);
+        }
+
+        features
+    }
+
+    /// Exports a compact list of nodes (in WGS84) and edges (with lengths in meters), suitable for
+    /// loading into routing or network analysis tools.
+    pub fn to_network(&self, map: &MapModel) -> Network {
+        let nodes = sorted_values(&self.nodes)
+            .into_iter()
+            .map(|node| {
+                let mut point = node.point;
+                map.mercator.to_wgs84_in_place(&mut point);
+                NetworkNode {
+                    id: node.id,
+                    lon: point.x(),
+                    lat: point.y(),
+                }
+            })
+            .collect();
+        let edges = sorted_values(&self.edges)
+            .into_iter()
+            .map(|edge| NetworkEdge {
+                id: edge.id,
+                node1: edge.node1,
+                node2: edge.node2,
+                length: edge.linestring.euclidean_length(),
+                roads: sorted(edge.roads.iter().cloned()),
+            })
+            .collect();
+        Network { nodes, edges }
+    }
+
+    /// Builds a network for routing over the current state of the graph. An edge can only be
+    /// travelled in a direction that all of its roads allow.
+    pub fn to_routing_network(&self, map: &MapModel) -> route::Network {
+        let mut network = route::Network::new();
+        let mut node_to_idx = HashMap::new();
+        for node in sorted_values(&self.nodes) {
+            let idx = network.add_node(node.point.into());
+            node_to_idx.insert(node.id, idx);
+            for i in &node.intersections {
+                network.add_intersection(*i, idx);
+            }
+        }
+        for edge in sorted_values(&self.edges) {
+            let roads = sorted(edge.roads.iter().cloned());
+            let mut forwards = true;
+            let mut backwards = true;
+            for r in &roads {
+                let (fwd, back) = route::allowed_directions(&map.roads[r.0].tags);
+                forwards &= fwd;
+                backwards &= back;
+            }
+            network.add_edge(
+                node_to_idx[&edge.node1],
+                node_to_idx[&edge.node2],
+                edge.linestring.clone(),
+                forwards,
+                backwards,
+                roads,
+            );
+        }
+        network
     }
 
-    pub fn trace_graph_loop(&mut self, node: usize, keep_last_point: bool) {
+    /// Finds a short loop starting and ending at `node`, then collapses all of the loop's nodes
+    /// into one new node at their centroid. On failure, the graph may be partly modified, so
+    /// callers should restore a copy.
+    pub fn trace_graph_loop(&mut self, node: usize, keep_last_point: bool) -> Result<()> {
         // Find a loop on this node
-        let Some(nodes) = self.find_cycle(NodeID(node)) else {
-            info!("no loop");
-            return;
+        let Some(nodes) = self.find_cycle(NodeID(node))? else {
+            bail!("No loop found from {:?}", NodeID(node));
         };
         info!("Got path {:?}", nodes);
 
         // Calculate the centroid of the nodes in this loop
-        let centroid = average(nodes.iter().map(|n| self.nodes[n].point).collect());
+        let mut points = Vec::new();
+        for n in &nodes {
+            points.push(self.node(*n)?.point);
+        }
+        let centroid = average(points);
         let new_node = self.new_node_id();
 
         // Remove the edges in this loop
-        for e in self.nodes_to_edges(&nodes) {
+        for e in self.nodes_to_edges(&nodes)? {
             info!("Removing {:?}", e);
-            self.remove_edge(e);
+            self.remove_edge(e)?;
         }
 
         // Remove all the old nodes, create one new one
         let mut intersections = HashSet::new();
         let mut surviving_edges = HashSet::new();
         for n in nodes.into_iter().skip(1) {
-            let old_node = self.nodes.remove(&n).unwrap();
+            let Some(old_node) = self.nodes.remove(&n) else {
+                bail!("{:?} is in the loop twice", n);
+            };
             intersections.extend(old_node.intersections);
 
-            // For any edge connected to the old node, connect it instead to our new merged node,
-            // fixing up the geometry
+            // For any edge connected to the old node, connect it instead to our new merged node
             for e in old_node.edges {
                 info!("fix up surviving edge {:?}", e);
-                let fix_edge = self.edges.get_mut(&e).unwrap();
+                let Some(fix_edge) = self.edges.get_mut(&e) else {
+                    bail!("{:?} refers to missing {:?}", old_node.id, e);
+                };
                 surviving_edges.insert(e);
 
                 if fix_edge.node1 == old_node.id {
                     fix_edge.node1 = new_node;
-                    // TODO If that last point is a fake centroid from a previous round, it'll look
-                    // odd
-                    if keep_last_point {
-                        fix_edge.linestring.0.insert(0, centroid.into());
-                    } else {
-                        fix_edge.linestring.0[0] = centroid.into();
-                    }
                 } else {
                     fix_edge.node2 = new_node;
-                    if keep_last_point {
-                        fix_edge.linestring.0.push(centroid.into());
-                    } else {
-                        fix_edge.linestring.0.pop();
-                        fix_edge.linestring.0.push(centroid.into());
-                    }
                 }
 
                 // It becomes a loop; totally nuke it
                 if fix_edge.node1 == fix_edge.node2 {
                     info!("edge became degenerate, removing {:?}", fix_edge.id);
-                    self.edges.remove(&e).unwrap();
+                    self.edges.remove(&e);
                     surviving_edges.remove(&e);
                 }
             }
@@ -177,15 +511,98 @@ impl Graph {
             new_node,
             Node {
                 id: new_node,
-                edges: surviving_edges,
+                edges: surviving_edges.clone(),
 
                 point: centroid,
                 intersections,
             },
         );
+
+        // Only fix up the geometry now, once both ends of every edge point to surviving nodes.
+        // Keeping the last point means not trimming anything, just connecting straight to the
+        // centroid.
+        let trim_meters = if keep_last_point { 0.0 } else { TRIM_METERS };
+        for e in surviving_edges {
+            let edge = self.edge(e)?;
+            let pt1 = self.node(edge.node1)?.point;
+            let pt2 = self.node(edge.node2)?.point;
+            self.edges
+                .get_mut(&e)
+                .unwrap()
+                .reshape_ends(pt1, pt2, trim_meters);
+        }
+
+        self.validate()
+    }
+
+    /// Checks that nodes and edges refer to each other consistently, that nothing refers to a
+    /// missing ID, and that the geometry of every edge ends at its nodes. Edges that loop back to
+    /// the same node are fine; real road networks have them. Reports every problem found.
+    pub fn validate(&self) -> Result<()> {
+        let mut problems = Vec::new();
+
+        for (id, edge) in &self.edges {
+            if *id != edge.id {
+                problems.push(format!("{:?} is stored as {:?}", edge.id, id));
+            }
+            if edge.roads.is_empty() {
+                problems.push(format!("{:?} has no roads", id));
+            }
+            if edge.linestring.0.len() < 2 {
+                problems.push(format!("{:?} has fewer than 2 points", id));
+                continue;
+            }
+
+            let endpoints = [
+                (edge.node1, edge.linestring.0[0]),
+                (edge.node2, *edge.linestring.0.last().unwrap()),
+            ];
+            for (n, endpoint) in endpoints {
+                let Some(node) = self.nodes.get(&n) else {
+                    problems.push(format!("{:?} refers to missing {:?}", id, n));
+                    continue;
+                };
+                if !node.edges.contains(id) {
+                    problems.push(format!("{:?} doesn't list {:?}", n, id));
+                }
+                if Point::from(endpoint).euclidean_distance(&node.point) > ENDPOINT_EPSILON {
+                    problems.push(format!("{:?} doesn't end at {:?}", id, n));
+                }
+            }
+        }
+
+        for (id, node) in &self.nodes {
+            if *id != node.id {
+                problems.push(format!("{:?} is stored as {:?}", node.id, id));
+            }
+            for e in &node.edges {
+                match self.edges.get(e) {
+                    Some(edge) => {
+                        if edge.node1 != *id && edge.node2 != *id {
+                            problems.push(format!("{:?} lists {:?}, which isn't attached", id, e));
+                        }
+                    }
+                    None => problems.push(format!("{:?} refers to missing {:?}", id, e)),
+                }
+            }
+        }
+
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            bail!("Graph is invalid: {}", problems.join(", "))
+        }
+    }
+
+    fn node(&self, n: NodeID) -> Result<&Node> {
+        self.nodes.get(&n).ok_or_else(|| anyhow!("Unknown {:?}", n))
+    }
+
+    fn edge(&self, e: EdgeID) -> Result<&Edge> {
+        self.edges.get(&e).ok_or_else(|| anyhow!("Unknown {:?}", e))
     }
 
-    fn find_cycle(&self, on_node: NodeID) -> Option<Vec<NodeID>> {
+    fn find_cycle(&self, on_node: NodeID) -> Result<Option<Vec<NodeID>>> {
         // Offline, this is an awful approach, but I want to move on
         let mut queue: Vec<Vec<NodeID>> = Vec::new();
         queue.push(vec![on_node]);
@@ -193,7 +610,7 @@ impl Graph {
         while let Some(current_path) = queue.pop() {
             let last_node = *current_path.last().unwrap();
             if current_path.len() > 2 && last_node == on_node {
-                return Some(current_path);
+                return Ok(Some(current_path));
             }
 
             // Limit length
@@ -201,8 +618,8 @@ impl Graph {
                 continue;
             }
 
-            for edge in &self.nodes[&last_node].edges {
-                let next_node = self.edges[edge].other_node(last_node);
+            for edge in &self.node(last_node)?.edges {
+                let next_node = self.edge(*edge)?.other_node(last_node)?;
                 // Don't double-back
                 if current_path.contains(&next_node) {
                     // Unless we found the loop
@@ -221,31 +638,103 @@ impl Graph {
             queue.sort_by_key(|path| path.len());
         }
 
-        None
+        Ok(None)
     }
 
-    fn nodes_to_edges(&self, path: &Vec<NodeID>) -> Vec<EdgeID> {
+    fn nodes_to_edges(&self, path: &Vec<NodeID>) -> Result<Vec<EdgeID>> {
         let mut edges = Vec::new();
         for pair in path.windows(2) {
-            edges.push(
-                *self.nodes[&pair[0]]
-                    .edges
-                    .iter()
-                    .find(|e| self.edges[e].other_node(pair[0]) == pair[1])
-                    .unwrap(),
-            );
+            let mut found = None;
+            for e in &self.node(pair[0])?.edges {
+                if self.edge(*e)?.other_node(pair[0])? == pair[1] {
+                    found = Some(*e);
+                    break;
+                }
+            }
+            let Some(e) = found else {
+                bail!("No edge between {:?} and {:?}", pair[0], pair[1]);
+            };
+            edges.push(e);
         }
-        edges
+        Ok(edges)
     }
 
-    fn remove_edge(&mut self, e: EdgeID) {
-        let edge = self.edges.remove(&e).unwrap();
+    fn remove_edge(&mut self, e: EdgeID) -> Result<()> {
+        let Some(edge) = self.edges.remove(&e) else {
+            bail!("Can't remove unknown {:?}", e);
+        };
         for n in [edge.node1, edge.node2] {
-            assert!(self.nodes.get_mut(&n).unwrap().edges.remove(&e));
+            let Some(node) = self.nodes.get_mut(&n) else {
+                bail!("{:?} refers to missing {:?}", e, n);
+            };
+            if !node.edges.remove(&e) {
+                bail!("{:?} doesn't list {:?}", n, e);
+            }
         }
+        Ok(())
     }
 }
 
+#[derive(Serialize, Tsify)]
+pub struct RenderedGraph<'a> {
+    #[serde(flatten)]
+    graph: &'a Graph,
+    node_components: BTreeMap<NodeID, usize>,
+    edge_components: BTreeMap<EdgeID, usize>,
+}
+
+#[derive(Serialize)]
+pub struct Network {
+    nodes: Vec<NetworkNode>,
+    edges: Vec<NetworkEdge>,
+}
+
+#[derive(Serialize)]
+struct NetworkNode {
+    id: NodeID,
+    lon: f64,
+    lat: f64,
+}
+
+#[derive(Serialize)]
+struct NetworkEdge {
+    id: EdgeID,
+    node1: NodeID,
+    node2: NodeID,
+    length: f64,
+    roads: Vec<RoadID>,
+}
+
+/// When roads disagree about a tag, keep every distinct value, separated by semicolons.
+fn merge_tags<'a>(roads: impl Iterator<Item = &'a Road>) -> BTreeMap<String, String> {
+    let mut values: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for road in roads {
+        for (k, v) in &road.tags.0 {
+            values
+                .entry(k.to_string())
+                .or_default()
+                .insert(v.to_string());
+        }
+    }
+    values
+        .into_iter()
+        .map(|(k, v)| (k, v.into_iter().collect::<Vec<_>>().join(";")))
+        .collect()
+}
+
+// Keep exports stable, despite the HashMaps
+fn sorted_values<K: Ord, V>(map: &HashMap<K, V>) -> Vec<&V> {
+    let mut pairs: Vec<(&K, &V)> = map.iter().collect();
+    pairs.sort_by(|a, b| a.0.cmp(b.0));
+    pairs.into_iter().map(|(_, v)| v).collect()
+}
+
+fn sorted<T: Ord>(items: impl Iterator<Item = T>) -> Vec<T> {
+    let mut list: Vec<T> = items.collect();
+    list.sort();
+    list
+}
+
 fn average(pts: Vec<Point>) -> Point {
     // TODO Centroid?
     let mut x = 0.0;
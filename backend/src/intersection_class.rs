@@ -0,0 +1,201 @@
+use geo::EuclideanLength;
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+
+use crate::cross_section::is_footway;
+use crate::math::point_and_angle_at;
+use crate::nodes::NodeKind;
+use crate::route::allowed_directions;
+use crate::{Intersection, MapModel, Road, RoadID};
+
+/// How far along each leg to look for the leg's direction, in meters
+const BEARING_DISTANCE: f64 = 10.0;
+/// Stop signs and signals on a road within this many meters of the intersection control it
+const CONTROL_RADIUS: f64 = 20.0;
+
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+pub struct Classification {
+    pub kind: IntersectionKind,
+    /// The strongest control of any leg
+    pub control: ControlType,
+    /// Ordered clockwise, starting from north
+    pub legs: Vec<Leg>,
+}
+
+/// Footways and paths don't count as legs, unless the intersection only has those
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "snake_case")]
+pub enum IntersectionKind {
+    DeadEnd,
+    /// Only two roads meet, so it's just a bend or a change in tags
+    PassThrough,
+    TJunction,
+    FourWay,
+    MultiLeg,
+    RoundaboutMember,
+}
+
+/// Ordered from weakest to strongest
+#[derive(Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "snake_case")]
+pub enum ControlType {
+    Uncontrolled,
+    GiveWay,
+    Stop,
+    Signals,
+}
+
+#[derive(Clone, Serialize, Deserialize, Tsify)]
+pub struct Leg {
+    pub road: RoadID,
+    /// Degrees clockwise from north, pointing away from the intersection
+    pub bearing: f64,
+    /// Traffic can enter the intersection from this leg
+    pub incoming: bool,
+    /// Traffic can leave the intersection on this leg
+    pub outgoing: bool,
+    pub control: ControlType,
+    pub footway: bool,
+}
+
+pub fn classify_all(map: &mut MapModel) {
+    let results: Vec<Classification> = map.intersections.iter().map(|i| classify(map, i)).collect();
+    for (i, classification) in map.intersections.iter_mut().zip(results) {
+        i.classification = Some(classification);
+    }
+}
+
+fn classify(map: &MapModel, i: &Intersection) -> Classification {
+    let signals_here = i.kinds.contains(&NodeKind::TrafficSignals);
+
+    let mut legs = Vec::new();
+    for r in &i.roads {
+        let road = &map.roads[r.0];
+        // A road looping back to the same intersection is two legs
+        for at_start in [true, false] {
+            if (at_start && road.src_i != i.id) || (!at_start && road.dst_i != i.id) {
+                continue;
+            }
+            let Some(bearing) = bearing(road, at_start) else {
+                continue;
+            };
+            let (incoming, outgoing) = directions(road, at_start);
+            let control = if signals_here {
+                ControlType::Signals
+            } else {
+                leg_control(road, at_start)
+            };
+            legs.push(Leg {
+                road: road.id,
+                bearing,
+                incoming,
+                outgoing,
+                control,
+                footway: is_footway(&road.tags),
+            });
+        }
+    }
+    legs.sort_by(|a, b| a.bearing.partial_cmp(&b.bearing).unwrap());
+
+    let roundabout = i.roads.iter().any(|r| {
+        map.roads[r.0]
+            .tags
+            .is_any("junction", vec!["roundabout", "circular"])
+    });
+    let mut num_legs = legs.iter().filter(|leg| !leg.footway).count();
+    if num_legs == 0 {
+        num_legs = legs.len();
+    }
+    let kind = if roundabout {
+        IntersectionKind::RoundaboutMember
+    } else {
+        match num_legs {
+            0 | 1 => IntersectionKind::DeadEnd,
+            2 => IntersectionKind::PassThrough,
+            3 => IntersectionKind::TJunction,
+            4 => IntersectionKind::FourWay,
+            _ => IntersectionKind::MultiLeg,
+        }
+    };
+
+    let mut control = legs
+        .iter()
+        .map(|leg| leg.control)
+        .fold(ControlType::Uncontrolled, |a, b| if b > a { b } else { a });
+    // Entering a roundabout implies giving way
+    if kind == IntersectionKind::RoundaboutMember && control == ControlType::Uncontrolled {
+        control = ControlType::GiveWay;
+    }
+
+    Classification {
+        kind,
+        control,
+        legs,
+    }
+}
+
+/// The direction of the road leaving the intersection, in degrees clockwise from north
+fn bearing(road: &Road, at_start: bool) -> Option<f64> {
+    let length = road.linestring.euclidean_length();
+    let dist = BEARING_DISTANCE.min(length);
+    let (from, to) = if at_start {
+        (
+            road.linestring.0.first()?,
+            point_and_angle_at(&road.linestring, dist)?.0,
+        )
+    } else {
+        (
+            road.linestring.0.last()?,
+            point_and_angle_at(&road.linestring, length - dist)?.0,
+        )
+    };
+    if *from == to {
+        return None;
+    }
+    // Mercator Y points south
+    let bearing = (to.x - from.x).atan2(from.y - to.y).to_degrees();
+    Some(bearing.rem_euclid(360.0))
+}
+
+/// Returns whether traffic can enter and leave the intersection along this road
+fn directions(road: &Road, at_start: bool) -> (bool, bool) {
+    if is_footway(&road.tags) {
+        return (true, true);
+    }
+    let (forwards, backwards) = allowed_directions(&road.tags);
+    // Traffic going forwards leaves the start of the road and enters at the end
+    if at_start {
+        (backwards, forwards)
+    } else {
+        (forwards, backwards)
+    }
+}
+
+/// Looks for stop signs, give way signs and signals on the road near this end
+fn leg_control(road: &Road, at_start: bool) -> ControlType {
+    let length = road.linestring.euclidean_length();
+    let mut control = ControlType::Uncontrolled;
+    for node in &road.nodes {
+        let dist = if at_start {
+            node.dist_along
+        } else {
+            length - node.dist_along
+        };
+        if dist > CONTROL_RADIUS {
+            continue;
+        }
+        let node_control = if node.is(NodeKind::TrafficSignals) {
+            ControlType::Signals
+        } else if node.is(NodeKind::Stop) {
+            ControlType::Stop
+        } else if node.is(NodeKind::GiveWay) {
+            ControlType::GiveWay
+        } else {
+            continue;
+        };
+        if node_control > control {
+            control = node_control;
+        }
+    }
+    control
+}
@@ -1,48 +1,125 @@
+use std::collections::HashMap;
+
 use geo::{BooleanOps, MultiPolygon, Polygon};
 use serde::Serialize;
+use tsify::Tsify;
 
 use crate::math::{buffer_linestring, union_all};
-use crate::{IntersectionID, MapModel};
+use crate::{IntersectionID, MapModel, RoadID};
 
-#[derive(Serialize)]
+#[derive(Serialize, Tsify)]
+#[serde(rename = "IntersectionGeometryOutput")]
 pub struct Output {
-    thick_roads: Vec<Polygon>,
-    overlaps: Vec<MultiPolygon>,
-    unioned: MultiPolygon,
+    pub thick_roads: Vec<Polygon>,
+    pub overlaps: Vec<MultiPolygon>,
+    pub unioned: MultiPolygon,
 }
 
 pub fn find_intersection_geometry(map: &MapModel, i: IntersectionID) -> Output {
     let half_buffer_meters = 1.0;
 
-    let mut thick_roads = Vec::new();
+    let mut thick_roads = HashMap::new();
     for r in &map.intersections[i.0].roads {
         if let Some(polygon) = buffer_linestring(
             &map.roads[r.0].linestring,
             half_buffer_meters,
             half_buffer_meters,
         ) {
-            thick_roads.push(polygon);
+            thick_roads.insert(*r, polygon);
         } else {
             warn!("Couldn't buffer a road");
         }
     }
 
-    // Take every pair of roads (TODO just adjacent), find their intersection ("overlap" for
-    // sanity), then union all of that.
+    // Take every pair of adjacent roads, find their intersection ("overlap" for sanity), then
+    // union all of that.
     let mut overlaps = Vec::new();
-    for idx1 in 0..thick_roads.len() {
-        for idx2 in 0..thick_roads.len() {
-            if idx1 != idx2 {
-                let overlap = thick_roads[idx1].intersection(&thick_roads[idx2]);
-                overlaps.push(overlap);
-            }
+    for (r1, r2) in road_pairs(map, i) {
+        if let (Some(p1), Some(p2)) = (thick_roads.get(&r1), thick_roads.get(&r2)) {
+            overlaps.push(p1.intersection(p2));
         }
     }
     let unioned = union_all(overlaps.clone());
 
     Output {
-        thick_roads,
+        thick_roads: map.intersections[i.0]
+            .roads
+            .iter()
+            .filter_map(|r| thick_roads.remove(r))
+            .collect(),
         overlaps,
         unioned,
     }
 }
+
+/// Like `find_intersection_geometry`, but buffers each road by its calculated width, and just
+/// returns the unioned overlaps. None for intersections where no roads overlap, like dead-ends.
+pub fn intersection_polygon(map: &MapModel, i: IntersectionID) -> Option<MultiPolygon> {
+    let half_buffer_meters = 1.0;
+
+    let mut thick_roads = HashMap::new();
+    for r in &map.intersections[i.0].roads {
+        let road = &map.roads[r.0];
+        if let Some(polygon) = road
+            .polygon
+            .clone()
+            .or_else(|| buffer_linestring(&road.linestring, half_buffer_meters, half_buffer_meters))
+        {
+            thick_roads.insert(*r, polygon);
+        }
+    }
+
+    let mut overlaps = Vec::new();
+    for (r1, r2) in road_pairs(map, i) {
+        if let (Some(p1), Some(p2)) = (thick_roads.get(&r1), thick_roads.get(&r2)) {
+            overlaps.push(p1.intersection(p2));
+        }
+    }
+    let unioned = union_all(overlaps);
+    if unioned.0.is_empty() {
+        None
+    } else {
+        Some(unioned)
+    }
+}
+
+/// The pairs of roads whose overlaps form the intersection. Once an intersection is classified,
+/// only roads next to each other going clockwise are paired, since opposite roads only overlap
+/// in the middle anyway. Otherwise every pair is used.
+fn road_pairs(map: &MapModel, i: IntersectionID) -> Vec<(RoadID, RoadID)> {
+    let intersection = &map.intersections[i.0];
+    let mut pairs = Vec::new();
+    if let Some(ref classification) = intersection.classification {
+        let legs = &classification.legs;
+        // Two legs only make one pair
+        let num_pairs = if legs.len() == 2 { 1 } else { legs.len() };
+        for idx in 0..num_pairs {
+            let r1 = legs[idx].road;
+            let r2 = legs[(idx + 1) % legs.len()].road;
+            // A road looping back to the intersection doesn't overlap itself
+            if r1 != r2 {
+                pairs.push((r1, r2));
+            }
+        }
+        // Roads too short to find a bearing for aren't legs. Their neighbours are unknown, so
+        // pair them with every other road rather than leave them out of the polygon.
+        for r1 in &intersection.roads {
+            if legs.iter().any(|leg| leg.road == *r1) {
+                continue;
+            }
+            for r2 in &intersection.roads {
+                if r1 != r2 && !pairs.contains(&(*r1, *r2)) && !pairs.contains(&(*r2, *r1)) {
+                    pairs.push((*r1, *r2));
+                }
+            }
+        }
+    } else {
+        let roads = &intersection.roads;
+        for idx1 in 0..roads.len() {
+            for idx2 in (idx1 + 1)..roads.len() {
+                pairs.push((roads[idx1], roads[idx2]));
+            }
+        }
+    }
+    pairs
+}
@@ -0,0 +1,447 @@
+use geo::{LineString, Polygon};
+use serde::Serialize;
+use tsify::Tsify;
+use utils::Tags;
+
+use crate::math::{offset_band, offset_linestring};
+use crate::Road;
+
+// Nominal widths, before scaling lanes to fit the carriageway
+const DRIVING_WIDTH: f64 = 3.0;
+const BUS_WIDTH: f64 = 3.0;
+const CYCLE_WIDTH: f64 = 1.5;
+const PARKING_WIDTH: f64 = 2.2;
+
+pub struct Lane {
+    pub kind: LaneKind,
+    pub direction: LaneDirection,
+    /// From `turn:lanes`, like "left;through"
+    pub turn: Option<String>,
+    /// Sideways offsets of the lane's edges from the road's centerline in meters, negative to the
+    /// left
+    pub left: f64,
+    pub right: f64,
+    pub polygon: Polygon,
+    pub center: LineString,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Tsify)]
+#[serde(rename_all = "snake_case")]
+pub enum LaneKind {
+    Driving,
+    Bus,
+    Cycle,
+    Parking,
+}
+
+/// Relative to the direction the road's linestring points
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Tsify)]
+#[serde(rename_all = "snake_case")]
+pub enum LaneDirection {
+    Forward,
+    Backward,
+    Both,
+    /// Parking
+    None,
+}
+
+struct LaneSpec {
+    kind: LaneKind,
+    direction: LaneDirection,
+    turn: Option<String>,
+    width: f64,
+}
+
+/// Divides a road's carriageway into lanes from its tags, ordered from the left edge to the right.
+/// The lanes are scaled to fill the carriageway found by the cross-section. Assumes right-hand
+/// traffic.
+pub fn road_lanes(road: &Road) -> Vec<Lane> {
+    let Some(ref cross_section) = road.cross_section else {
+        return Vec::new();
+    };
+    let specs = lane_specs(&road.tags);
+    let total_nominal: f64 = specs.iter().map(|spec| spec.width).sum();
+    let total = cross_section.carriageway_left + cross_section.carriageway_right;
+    if total_nominal == 0.0 || total == 0.0 {
+        return Vec::new();
+    }
+    let scale = total / total_nominal;
+
+    let mut lanes = Vec::new();
+    let mut left = -cross_section.carriageway_left;
+    for spec in specs {
+        let right = left + spec.width * scale;
+        if let (Some(polygon), Some(center)) = (
+            offset_band(&road.linestring, left, right),
+            offset_linestring(&road.linestring, (left + right) / 2.0),
+        ) {
+            lanes.push(Lane {
+                kind: spec.kind,
+                direction: spec.direction,
+                turn: spec.turn,
+                left,
+                right,
+                polygon,
+                center,
+            });
+        }
+        left = right;
+    }
+    lanes
+}
+
+fn lane_specs(tags: &Tags) -> Vec<LaneSpec> {
+    let oneway = if tags.is_any("oneway", vec!["yes", "1", "true"]) {
+        Some(LaneDirection::Forward)
+    } else if tags.is_any("oneway", vec!["-1", "reverse"]) {
+        Some(LaneDirection::Backward)
+    } else {
+        None
+    };
+
+    let bus_left = side_is(tags, "busway", "left", &["lane"])
+        || (oneway.is_none() && tags.is("busway", "lane"));
+    let bus_right = side_is(tags, "busway", "right", &["lane"]) || tags.is("busway", "lane");
+    let cycle_left = side_is(tags, "cycleway", "left", &["lane"])
+        || (oneway.is_none() && tags.is("cycleway", "lane"));
+    let cycle_right = side_is(tags, "cycleway", "right", &["lane"]) || tags.is("cycleway", "lane");
+    let parking_left = has_parking(tags, "left");
+    let parking_right = has_parking(tags, "right");
+
+    // Traffic next to the left edge goes backward, except on one-way roads
+    let left_direction = oneway.unwrap_or(LaneDirection::Backward);
+    let right_direction = oneway.unwrap_or(LaneDirection::Forward);
+
+    // Bus lanes count towards `lanes`, taken from the direction they carry
+    let (mut backward, mut forward) = driving_lane_counts(tags, oneway);
+    for (bus, direction) in [(bus_left, left_direction), (bus_right, right_direction)] {
+        if !bus {
+            continue;
+        }
+        let count = if direction == LaneDirection::Forward {
+            &mut forward
+        } else {
+            &mut backward
+        };
+        // Keep at least one driving lane in a direction that has any
+        if *count > 0 {
+            *count = (*count - 1).max(1);
+        }
+    }
+
+    let mut specs = Vec::new();
+    if parking_left {
+        specs.push(spec(LaneKind::Parking, LaneDirection::None, None));
+    }
+    if cycle_left {
+        specs.push(spec(LaneKind::Cycle, left_direction, None));
+    }
+    if bus_left {
+        specs.push(spec(LaneKind::Bus, left_direction, None));
+    }
+
+    if backward + forward == 1 && oneway.is_none() {
+        specs.push(spec(LaneKind::Driving, LaneDirection::Both, None));
+    } else {
+        // Both lists go left to right from the point of view of someone driving in that
+        // direction. Backward lanes are seen from the other end of the road, so reverse them.
+        let backward_key = if oneway == Some(LaneDirection::Backward) {
+            "turn:lanes"
+        } else {
+            "turn:lanes:backward"
+        };
+        let mut backward_turns = turns(tags, backward_key, backward);
+        backward_turns.reverse();
+        for turn in backward_turns {
+            specs.push(spec(LaneKind::Driving, LaneDirection::Backward, turn));
+        }
+
+        let forward_key = if oneway == Some(LaneDirection::Forward) {
+            "turn:lanes"
+        } else {
+            "turn:lanes:forward"
+        };
+        for turn in turns(tags, forward_key, forward) {
+            specs.push(spec(LaneKind::Driving, LaneDirection::Forward, turn));
+        }
+    }
+
+    if bus_right {
+        specs.push(spec(LaneKind::Bus, right_direction, None));
+    }
+    if cycle_right {
+        specs.push(spec(LaneKind::Cycle, right_direction, None));
+    }
+    if parking_right {
+        specs.push(spec(LaneKind::Parking, LaneDirection::None, None));
+    }
+    specs
+}
+
+/// Returns the number of backward and forward driving lanes
+fn driving_lane_counts(tags: &Tags, oneway: Option<LaneDirection>) -> (usize, usize) {
+    let count = |key: &str| tags.get(key).and_then(|x| x.parse::<usize>().ok());
+    match oneway {
+        Some(LaneDirection::Forward) => (0, count("lanes").unwrap_or(1).max(1)),
+        Some(_) => (count("lanes").unwrap_or(1).max(1), 0),
+        None => {
+            let total = count("lanes").unwrap_or(2).max(1);
+            match (count("lanes:backward"), count("lanes:forward")) {
+                (Some(backward), Some(forward)) => (backward, forward),
+                (Some(backward), None) => (backward, total.saturating_sub(backward)),
+                (None, Some(forward)) => (total.saturating_sub(forward), forward),
+                (None, None) => (total / 2, total - total / 2),
+            }
+        }
+    }
+}
+
+/// Splits `turn:lanes` values, padding or truncating to `count` lanes
+fn turns(tags: &Tags, key: &str, count: usize) -> Vec<Option<String>> {
+    let mut turns: Vec<Option<String>> = tags
+        .get(key)
+        .map(|value| {
+            value
+                .split('|')
+                .map(|turn| {
+                    if turn.is_empty() || turn == "none" {
+                        None
+                    } else {
+                        Some(turn.to_string())
+                    }
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    turns.resize(count, None);
+    turns
+}
+
+/// Checks `key:side` and `key:both`
+fn side_is(tags: &Tags, key: &str, side: &str, values: &[&str]) -> bool {
+    [format!("{key}:{side}"), format!("{key}:both")]
+        .iter()
+        .any(|k| tags.is_any(k, values.to_vec()))
+}
+
+/// Understands both `parking:side=lane` and the older `parking:lane:side=parallel` scheme
+fn has_parking(tags: &Tags, side: &str) -> bool {
+    side_is(tags, "parking", side, &["lane"])
+        || side_is(
+            tags,
+            "parking:lane",
+            side,
+            &["parallel", "diagonal", "perpendicular", "marked"],
+        )
+}
+
+fn spec(kind: LaneKind, direction: LaneDirection, turn: Option<String>) -> LaneSpec {
+    let width = match kind {
+        LaneKind::Driving => DRIVING_WIDTH,
+        LaneKind::Bus => BUS_WIDTH,
+        LaneKind::Cycle => CYCLE_WIDTH,
+        LaneKind::Parking => PARKING_WIDTH,
+    };
+    LaneSpec {
+        kind,
+        direction,
+        turn,
+        width,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use LaneDirection::{Backward, Both, Forward};
+    use LaneKind::{Bus, Cycle, Driving, Parking};
+
+    fn specs(pairs: &[(&str, &str)]) -> Vec<(LaneKind, LaneDirection, Option<String>)> {
+        let mut tags = Tags::default();
+        for (k, v) in pairs {
+            tags.insert(k.to_string(), v.to_string());
+        }
+        lane_specs(&tags)
+            .into_iter()
+            .map(|spec| (spec.kind, spec.direction, spec.turn))
+            .collect()
+    }
+
+    fn lane(
+        kind: LaneKind,
+        direction: LaneDirection,
+        turn: Option<&str>,
+    ) -> (LaneKind, LaneDirection, Option<String>) {
+        (kind, direction, turn.map(|t| t.to_string()))
+    }
+
+    #[test]
+    fn default_two_way() {
+        assert_eq!(
+            specs(&[("highway", "residential")]),
+            vec![lane(Driving, Backward, None), lane(Driving, Forward, None)]
+        );
+    }
+
+    #[test]
+    fn single_lane_two_way() {
+        assert_eq!(
+            specs(&[("highway", "residential"), ("lanes", "1")]),
+            vec![lane(Driving, Both, None)]
+        );
+    }
+
+    #[test]
+    fn odd_lanes_split() {
+        // The extra lane goes forward
+        assert_eq!(
+            specs(&[("lanes", "3")]),
+            vec![
+                lane(Driving, Backward, None),
+                lane(Driving, Forward, None),
+                lane(Driving, Forward, None),
+            ]
+        );
+        assert_eq!(
+            specs(&[("lanes", "3"), ("lanes:forward", "1")]),
+            vec![
+                lane(Driving, Backward, None),
+                lane(Driving, Backward, None),
+                lane(Driving, Forward, None),
+            ]
+        );
+        assert_eq!(
+            specs(&[("lanes", "4"), ("lanes:backward", "1")]),
+            vec![
+                lane(Driving, Backward, None),
+                lane(Driving, Forward, None),
+                lane(Driving, Forward, None),
+                lane(Driving, Forward, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn turn_lanes_both_directions() {
+        // Backward turns are listed from the other end, so they're reversed
+        assert_eq!(
+            specs(&[
+                ("lanes", "4"),
+                ("turn:lanes:backward", "left|through"),
+                ("turn:lanes:forward", "left|through;right"),
+            ]),
+            vec![
+                lane(Driving, Backward, Some("through")),
+                lane(Driving, Backward, Some("left")),
+                lane(Driving, Forward, Some("left")),
+                lane(Driving, Forward, Some("through;right")),
+            ]
+        );
+    }
+
+    #[test]
+    fn reversed_oneway_uses_turn_lanes() {
+        assert_eq!(
+            specs(&[
+                ("oneway", "-1"),
+                ("lanes", "2"),
+                ("turn:lanes", "left|none"),
+            ]),
+            vec![
+                lane(Driving, Backward, None),
+                lane(Driving, Backward, Some("left")),
+            ]
+        );
+    }
+
+    #[test]
+    fn turn_lanes_are_padded_and_truncated() {
+        assert_eq!(
+            specs(&[("oneway", "yes"), ("lanes", "3"), ("turn:lanes", "left")]),
+            vec![
+                lane(Driving, Forward, Some("left")),
+                lane(Driving, Forward, None),
+                lane(Driving, Forward, None),
+            ]
+        );
+        assert_eq!(
+            specs(&[("oneway", "yes"), ("turn:lanes", "left|through|right")]),
+            vec![lane(Driving, Forward, Some("left"))]
+        );
+    }
+
+    #[test]
+    fn bus_lanes_count_towards_lanes() {
+        assert_eq!(
+            specs(&[("oneway", "yes"), ("lanes", "3"), ("busway:right", "lane")]),
+            vec![
+                lane(Driving, Forward, None),
+                lane(Driving, Forward, None),
+                lane(Bus, Forward, None),
+            ]
+        );
+        // Both sides of a two-way road
+        assert_eq!(
+            specs(&[("lanes", "4"), ("busway", "lane")]),
+            vec![
+                lane(Bus, Backward, None),
+                lane(Driving, Backward, None),
+                lane(Driving, Forward, None),
+                lane(Bus, Forward, None),
+            ]
+        );
+        // Never leaves a direction without a driving lane
+        assert_eq!(
+            specs(&[("oneway", "yes"), ("lanes", "1"), ("busway:right", "lane")]),
+            vec![lane(Driving, Forward, None), lane(Bus, Forward, None)]
+        );
+        // A reversed one-way road only has backward lanes
+        assert_eq!(
+            specs(&[("oneway", "-1"), ("lanes", "2"), ("busway", "lane")]),
+            vec![lane(Driving, Backward, None), lane(Bus, Backward, None)]
+        );
+    }
+
+    #[test]
+    fn cycle_lanes() {
+        assert_eq!(
+            specs(&[("cycleway", "lane")]),
+            vec![
+                lane(Cycle, Backward, None),
+                lane(Driving, Backward, None),
+                lane(Driving, Forward, None),
+                lane(Cycle, Forward, None),
+            ]
+        );
+        // Only on the right of one-way roads
+        assert_eq!(
+            specs(&[("oneway", "yes"), ("cycleway", "lane")]),
+            vec![lane(Driving, Forward, None), lane(Cycle, Forward, None)]
+        );
+    }
+
+    #[test]
+    fn parking_schemes() {
+        let both = vec![
+            lane(Parking, LaneDirection::None, None),
+            lane(Driving, Backward, None),
+            lane(Driving, Forward, None),
+            lane(Parking, LaneDirection::None, None),
+        ];
+        assert_eq!(specs(&[("parking:both", "lane")]), both);
+        assert_eq!(specs(&[("parking:lane:both", "parallel")]), both);
+        assert_eq!(
+            specs(&[("parking:lane:right", "diagonal")]),
+            vec![
+                lane(Driving, Backward, None),
+                lane(Driving, Forward, None),
+                lane(Parking, LaneDirection::None, None),
+            ]
+        );
+        // Only actual parking lanes count
+        assert_eq!(
+            specs(&[("parking:lane:both", "no_stopping")]),
+            vec![lane(Driving, Backward, None), lane(Driving, Forward, None)]
+        );
+    }
+}
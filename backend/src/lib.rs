@@ -3,37 +3,57 @@ extern crate log;
 
 use std::sync::Once;
 
-use geo::{LineString, Point, Polygon};
+use geo::{Coord, LineString, Point, Polygon, Rect};
 use geojson::GeoJson;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 use utils::{Mercator, Tags};
 use wasm_bindgen::prelude::*;
 
+mod components;
+mod cross_section;
+mod crossings;
+mod draw;
+mod fgb;
 mod find_road_width;
 mod graph;
+mod intersection_class;
 mod intersection_geometry;
+mod lanes;
+mod markings;
 mod math;
+mod mvt;
+mod nodes;
+mod obstacles;
 mod output;
+mod png;
+mod render;
+mod route;
 mod scrape;
+mod snapshot;
+mod spatial;
+mod svg;
+mod types;
 
 static START: Once = Once::new();
 
 #[wasm_bindgen]
 pub struct MapModel {
-    #[allow(unused)]
     mercator: Mercator,
     roads: Vec<Road>,
     intersections: Vec<Intersection>,
     buildings: Vec<Building>,
+    obstacles: Vec<obstacles::Obstacle>,
+    index: spatial::SpatialIndex,
+    road_width_options: find_road_width::Options,
 
     // TODO Weird to embed like this, but easier to prototype
     graph: graph::Graph,
     graph_undo_stack: Vec<graph::Graph>,
 }
 
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct RoadID(pub usize);
-#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct IntersectionID(pub usize);
 
 pub struct Road {
@@ -45,11 +65,17 @@ pub struct Road {
     dst_i: IntersectionID,
     linestring: LineString,
     tags: Tags,
+    component: usize,
 
     // Derived a bit later
     max_left_width: Option<f64>,
     max_right_width: Option<f64>,
+    left_limited_by: Option<obstacles::ObstacleKind>,
+    right_limited_by: Option<obstacles::ObstacleKind>,
     polygon: Option<Polygon>,
+    cross_section: Option<cross_section::CrossSection>,
+    /// Tagged OSM nodes like crossings and traffic signals, sorted by distance along the road
+    nodes: Vec<nodes::RoadNode>,
 }
 
 pub struct Intersection {
@@ -57,6 +83,12 @@ pub struct Intersection {
     node: osm_reader::NodeID,
     point: Point,
     roads: Vec<RoadID>,
+    component: usize,
+    /// From the OSM node's tags, which are only kept if there's at least one kind
+    kinds: Vec<nodes::NodeKind>,
+    tags: Tags,
+    /// Set after scraping, once the intersection's roads are final
+    classification: Option<intersection_class::Classification>,
 }
 
 struct Building {
@@ -76,27 +108,107 @@ impl MapModel {
             console_log::init_with_level(log::Level::Info).unwrap();
         });
 
-        scrape::scrape_osm(input_bytes).map_err(err_to_js)
+        scrape::scrape_osm(input_bytes, &scrape::Options::default()).map_err(err_to_js)
+    }
+
+    /// Like the constructor, but also takes an object with any `scrape::Options`
+    #[wasm_bindgen(js_name = newWithOptions)]
+    pub fn new_with_options(input_bytes: &[u8], options: JsValue) -> Result<MapModel, JsValue> {
+        console_error_panic_hook::set_once();
+        START.call_once(|| {
+            console_log::init_with_level(log::Level::Info).unwrap();
+        });
+
+        let options: scrape::Options = serde_wasm_bindgen::from_value(options)?;
+        scrape::scrape_osm(input_bytes, &options).map_err(err_to_js)
+    }
+
+    /// Loads a map previously saved with `toSnapshot`, skipping all processing
+    #[wasm_bindgen(js_name = fromSnapshot)]
+    pub fn from_snapshot(bytes: &[u8]) -> Result<MapModel, JsValue> {
+        console_error_panic_hook::set_once();
+        START.call_once(|| {
+            console_log::init_with_level(log::Level::Info).unwrap();
+        });
+
+        snapshot::from_snapshot(bytes).map_err(err_to_js)
+    }
+
+    /// True if the bytes look like a snapshot, rather than OSM input
+    #[wasm_bindgen(js_name = isSnapshot)]
+    pub fn is_snapshot(bytes: &[u8]) -> bool {
+        snapshot::is_snapshot(bytes)
+    }
+
+    /// Saves the fully processed map, including the current graph, in a binary format
+    #[wasm_bindgen(js_name = toSnapshot)]
+    pub fn to_snapshot(&self) -> Result<Vec<u8>, JsValue> {
+        snapshot::to_snapshot(self).map_err(err_to_js)
+    }
+
+    /// Exports one layer (`centerlines`, `road_polygons`, `intersection_polygons` or
+    /// `buildings`) as FlatGeobuf bytes in WGS84
+    #[wasm_bindgen(js_name = exportFlatGeobuf)]
+    pub fn export_flatgeobuf(&self, layer: &str) -> Result<Vec<u8>, JsValue> {
+        let layer = fgb::Layer::from_name(layer).map_err(err_to_js)?;
+        fgb::export_layer(self, layer).map_err(err_to_js)
     }
 
     /// Returns a GeoJSON string
     #[wasm_bindgen()]
     pub fn render(&mut self) -> Result<String, JsValue> {
-        let mut features = Vec::new();
+        let out = serde_json::to_string(&self.render_geojson()).map_err(err_to_js)?;
+        Ok(out)
+    }
 
-        for r in &self.roads {
-            features.push(r.to_geojson());
-        }
-        for i in &self.intersections {
-            features.push(i.to_geojson());
-        }
-        for b in &self.buildings {
-            features.push(b.to_geojson());
-        }
+    /// Like `render`, but returns the GeoJSON object directly, avoiding a JSON string
+    #[wasm_bindgen(js_name = renderJs)]
+    pub fn render_js(&self) -> Result<types::JsRenderOutput, JsValue> {
+        to_js(&self.render_geojson())
+    }
 
-        let gj = GeoJson::from(features);
-        let out = serde_json::to_string(&gj).map_err(err_to_js)?;
-        Ok(out)
+    /// Returns a GeoJSON object with only the layers and features requested by an object of
+    /// `RenderOptions`
+    #[wasm_bindgen(js_name = renderLayers)]
+    pub fn render_layers(&self, options: JsValue) -> Result<types::JsRenderOutput, JsValue> {
+        let options: render::RenderOptions = serde_wasm_bindgen::from_value(options)?;
+        to_js(&render::render_layers(self, &options))
+    }
+
+    /// Returns an SVG document of the map, controlled by an object of `DrawOptions`
+    #[wasm_bindgen(js_name = renderSvg)]
+    pub fn render_svg(&self, options: JsValue) -> Result<String, JsValue> {
+        let options: draw::DrawOptions = serde_wasm_bindgen::from_value(options)?;
+        svg::render_svg(self, &options).map_err(err_to_js)
+    }
+
+    /// Like `renderSvg`, but returns PNG bytes
+    #[wasm_bindgen(js_name = renderPng)]
+    pub fn render_png(&self, options: JsValue) -> Result<Vec<u8>, JsValue> {
+        let options: draw::DrawOptions = serde_wasm_bindgen::from_value(options)?;
+        png::render_png(self, &options).map_err(err_to_js)
+    }
+
+    /// Returns PNG bytes of the `findRoadWidth` debug view, `width` pixels wide
+    #[wasm_bindgen(js_name = roadWidthPng)]
+    pub fn road_width_png(&self, r: usize, width: f64) -> Result<Vec<u8>, JsValue> {
+        self.road_width_debug_png(RoadID(r), width)
+            .map_err(err_to_js)
+    }
+
+    /// Returns PNG bytes of the `findIntersectionGeometry` debug view, `width` pixels wide
+    #[wasm_bindgen(js_name = intersectionGeometryPng)]
+    pub fn intersection_geometry_png(&self, i: usize, width: f64) -> Result<Vec<u8>, JsValue> {
+        self.intersection_geometry_debug_png(IntersectionID(i), width)
+            .map_err(err_to_js)
+    }
+
+    /// Returns a Mapbox Vector Tile, or an empty array if nothing is in the tile
+    #[wasm_bindgen(js_name = vectorTile)]
+    pub fn vector_tile(&self, z: u32, x: u32, y: u32) -> Result<Vec<u8>, JsValue> {
+        Ok(mvt::make_tile(self, z, x, y)
+            .map_err(err_to_js)?
+            .unwrap_or_default())
     }
 
     #[wasm_bindgen(js_name = findRoadWidth)]
@@ -106,6 +218,11 @@ impl MapModel {
         Ok(out)
     }
 
+    #[wasm_bindgen(js_name = findRoadWidthJs)]
+    pub fn find_road_width_js(&self, r: usize) -> Result<types::JsRoadWidthOutput, JsValue> {
+        to_js(&find_road_width::find_road_width(self, RoadID(r)))
+    }
+
     #[wasm_bindgen(js_name = findIntersectionGeometry)]
     pub fn find_intersection_geometry(&self, i: usize) -> Result<String, JsValue> {
         let obj = intersection_geometry::find_intersection_geometry(self, IntersectionID(i));
@@ -113,16 +230,171 @@ impl MapModel {
         Ok(out)
     }
 
+    #[wasm_bindgen(js_name = findIntersectionGeometryJs)]
+    pub fn find_intersection_geometry_js(
+        &self,
+        i: usize,
+    ) -> Result<types::JsIntersectionGeometryOutput, JsValue> {
+        to_js(&intersection_geometry::find_intersection_geometry(
+            self,
+            IntersectionID(i),
+        ))
+    }
+
+    /// Returns a GeoJSON object with painted road markings: centre lines, lane dividers, edge
+    /// lines and stop lines. Slow for large maps, since it finds every intersection polygon.
+    #[wasm_bindgen(js_name = renderMarkings)]
+    pub fn render_markings(&self) -> Result<types::JsRenderOutput, JsValue> {
+        let features: Vec<_> = markings::all_markings(self)
+            .iter()
+            .map(|m| m.to_geojson())
+            .collect();
+        to_js(&GeoJson::from(features))
+    }
+
+    /// Returns the shortest route between two intersections as a JSON string. If `simplified`,
+    /// routes over the current graph instead of the original roads.
+    #[wasm_bindgen(js_name = routeIntersections)]
+    pub fn route_intersections(
+        &self,
+        from: usize,
+        to: usize,
+        simplified: bool,
+    ) -> Result<String, JsValue> {
+        let network = self.routing_network(simplified);
+        let from = network
+            .intersection_endpoint(IntersectionID(from))
+            .map_err(err_to_js)?;
+        let to = network
+            .intersection_endpoint(IntersectionID(to))
+            .map_err(err_to_js)?;
+        let route = network.route(from, to).map_err(err_to_js)?;
+        let out = serde_json::to_string(&route).map_err(err_to_js)?;
+        Ok(out)
+    }
+
+    /// Like `routeIntersections`, but between two points (in Mercator), snapped to the closest
+    /// roads
+    #[wasm_bindgen(js_name = routePoints)]
+    pub fn route_points(
+        &self,
+        x1: f64,
+        y1: f64,
+        x2: f64,
+        y2: f64,
+        simplified: bool,
+    ) -> Result<String, JsValue> {
+        let network = self.routing_network(simplified);
+        let from = network.snap(Coord { x: x1, y: y1 }).map_err(err_to_js)?;
+        let to = network.snap(Coord { x: x2, y: y2 }).map_err(err_to_js)?;
+        let route = network.route(from, to).map_err(err_to_js)?;
+        let out = serde_json::to_string(&route).map_err(err_to_js)?;
+        Ok(out)
+    }
+
+    // Spatial queries. All points are in Mercator.
+    #[wasm_bindgen(js_name = nearestRoad)]
+    pub fn nearest_road(&self, x: f64, y: f64) -> Result<types::JsNearestRoad, JsValue> {
+        to_js(&self.index.nearest_road(self, Coord { x, y }))
+    }
+
+    #[wasm_bindgen(js_name = nearestIntersection)]
+    pub fn nearest_intersection(
+        &self,
+        x: f64,
+        y: f64,
+    ) -> Result<types::JsNearestIntersection, JsValue> {
+        to_js(&self.index.nearest_intersection(Coord { x, y }))
+    }
+
+    #[wasm_bindgen(js_name = nearestBuilding)]
+    pub fn nearest_building(&self, x: f64, y: f64) -> Result<types::JsNearestBuilding, JsValue> {
+        to_js(&self.index.nearest_building(self, Coord { x, y }))
+    }
+
+    /// Finds the closest point on any road
+    #[wasm_bindgen(js_name = snapToRoad)]
+    pub fn snap_to_road(&self, x: f64, y: f64) -> Result<types::JsRoadSnap, JsValue> {
+        to_js(&self.index.snap_to_road(self, Coord { x, y }))
+    }
+
+    /// Returns the roads, intersections and buildings touching a bounding box
+    #[wasm_bindgen(js_name = queryBbox)]
+    pub fn query_bbox(
+        &self,
+        x1: f64,
+        y1: f64,
+        x2: f64,
+        y2: f64,
+    ) -> Result<types::JsQueryResults, JsValue> {
+        let bbox = Rect::new(Coord { x: x1, y: y1 }, Coord { x: x2, y: y2 });
+        to_js(&self.index.query_bbox(self, bbox))
+    }
+
+    /// Like `queryBbox`, but for a polygon given as a flat array of its exterior's
+    /// `[x1, y1, x2, y2, ...]`
+    #[wasm_bindgen(js_name = queryPolygon)]
+    pub fn query_polygon(&self, exterior: &[f64]) -> Result<types::JsQueryResults, JsValue> {
+        let pairs = exterior.chunks_exact(2);
+        if !pairs.remainder().is_empty() {
+            return Err(err_to_js("The polygon needs an even number of coordinates"));
+        }
+        let polygon = Polygon::new(
+            LineString::new(
+                pairs
+                    .map(|pair| Coord {
+                        x: pair[0],
+                        y: pair[1],
+                    })
+                    .collect(),
+            ),
+            Vec::new(),
+        );
+        to_js(&self.index.query_polygon(self, &polygon))
+    }
+
     // Graph stuff
     #[wasm_bindgen(js_name = renderGraph)]
     pub fn render_graph(&self) -> String {
         self.graph.render()
     }
 
+    #[wasm_bindgen(js_name = renderGraphJs)]
+    pub fn render_graph_js(&self) -> Result<types::JsRenderedGraph, JsValue> {
+        to_js(&self.graph.render_output())
+    }
+
+    /// Returns a GeoJSON string in WGS84, with one feature per graph edge and node
+    #[wasm_bindgen(js_name = exportGraphGeoJson)]
+    pub fn export_graph_geojson(&self) -> Result<String, JsValue> {
+        let gj = self.graph.to_geojson(self);
+        let out = serde_json::to_string(&gj).map_err(err_to_js)?;
+        Ok(out)
+    }
+
+    /// Returns a JSON string with lists of graph nodes and edges, for routing tools
+    #[wasm_bindgen(js_name = exportGraphNetwork)]
+    pub fn export_graph_network(&self) -> Result<String, JsValue> {
+        let network = self.graph.to_network(self);
+        let out = serde_json::to_string(&network).map_err(err_to_js)?;
+        Ok(out)
+    }
+
     #[wasm_bindgen(js_name = traceGraphLoop)]
-    pub fn trace_graph_loop(&mut self, node: usize, keep_last_point: bool) {
-        self.graph_undo_stack.push(self.graph.clone());
-        self.graph.trace_graph_loop(node, keep_last_point)
+    pub fn trace_graph_loop(&mut self, node: usize, keep_last_point: bool) -> Result<(), JsValue> {
+        let backup = self.graph.clone();
+        if let Err(err) = self.graph.trace_graph_loop(node, keep_last_point) {
+            // Don't leave a partly modified graph around
+            self.graph = backup;
+            return Err(err_to_js(err));
+        }
+        self.graph_undo_stack.push(backup);
+        Ok(())
+    }
+
+    #[wasm_bindgen(js_name = validateGraph)]
+    pub fn validate_graph(&self) -> Result<(), JsValue> {
+        self.graph.validate().map_err(err_to_js)
     }
 
     #[wasm_bindgen(js_name = undoGraph)]
@@ -133,6 +405,109 @@ impl MapModel {
     }
 }
 
+// For native callers, like the CLI. These don't touch anything in the browser.
+impl MapModel {
+    /// Processes bytes of an osm.pbf or osm.xml file
+    pub fn from_osm(input_bytes: &[u8]) -> anyhow::Result<MapModel> {
+        scrape::scrape_osm(input_bytes, &scrape::Options::default())
+    }
+
+    pub fn load_snapshot(bytes: &[u8]) -> anyhow::Result<MapModel> {
+        snapshot::from_snapshot(bytes)
+    }
+
+    /// Loads a snapshot if the bytes are one, otherwise processes them as OSM input
+    pub fn load(bytes: &[u8]) -> anyhow::Result<MapModel> {
+        if snapshot::is_snapshot(bytes) {
+            Self::load_snapshot(bytes)
+        } else {
+            Self::from_osm(bytes)
+        }
+    }
+
+    pub fn save_snapshot(&self) -> anyhow::Result<Vec<u8>> {
+        snapshot::to_snapshot(self)
+    }
+
+    /// Draws the map as SVG, with the default layers and styles
+    pub fn to_svg(&self) -> anyhow::Result<String> {
+        svg::render_svg(self, &draw::DrawOptions::default())
+    }
+
+    /// Makes every non-empty vector tile covering the map between two zoom levels, passing each
+    /// one's z, x, y and bytes to `write`
+    pub fn write_vector_tiles(
+        &self,
+        min_zoom: u32,
+        max_zoom: u32,
+        mut write: impl FnMut(u32, u32, u32, Vec<u8>) -> anyhow::Result<()>,
+    ) -> anyhow::Result<()> {
+        for z in min_zoom..=max_zoom {
+            for (x, y) in mvt::tiles_covering(self, z)? {
+                if let Some(bytes) = mvt::make_tile(self, z, x, y)? {
+                    write(z, x, y, bytes)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Draws the map as PNG, with the default layers and styles
+    pub fn to_png(&self) -> anyhow::Result<Vec<u8>> {
+        png::render_png(self, &draw::DrawOptions::default())
+    }
+
+    pub fn road_width_debug_png(&self, r: RoadID, width: f64) -> anyhow::Result<Vec<u8>> {
+        if r.0 >= self.roads.len() {
+            anyhow::bail!("Unknown road {r:?}");
+        }
+        let out = find_road_width::find_road_width(self, r);
+        png::render_debug_png(self, width, |painter| draw::draw_road_width(&out, painter))
+    }
+
+    pub fn intersection_geometry_debug_png(
+        &self,
+        i: IntersectionID,
+        width: f64,
+    ) -> anyhow::Result<Vec<u8>> {
+        if i.0 >= self.intersections.len() {
+            anyhow::bail!("Unknown intersection {i:?}");
+        }
+        let out = intersection_geometry::find_intersection_geometry(self, i);
+        png::render_debug_png(self, width, |painter| {
+            draw::draw_intersection_geometry(&out, painter)
+        })
+    }
+
+    /// Exports every FlatGeobuf layer, returning each layer's name and bytes
+    pub fn export_all_flatgeobuf(&self) -> anyhow::Result<Vec<(&'static str, Vec<u8>)>> {
+        fgb::Layer::ALL
+            .into_iter()
+            .map(|layer| Ok((layer.name(), fgb::export_layer(self, layer)?)))
+            .collect()
+    }
+}
+
+impl MapModel {
+    fn render_geojson(&self) -> GeoJson {
+        render::render_layers(self, &render::RenderOptions::default())
+    }
+
+    fn routing_network(&self, simplified: bool) -> route::Network {
+        if simplified {
+            self.graph.to_routing_network(self)
+        } else {
+            route::Network::from_map(self)
+        }
+    }
+}
+
+/// Converts to a plain JS object, with the same structure as the JSON string methods produce
+fn to_js<T: Serialize, JS: JsCast>(value: &T) -> Result<JS, JsValue> {
+    let out = value.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?;
+    Ok(out.unchecked_into())
+}
+
 fn err_to_js<E: std::fmt::Display>(err: E) -> JsValue {
     JsValue::from_str(&err.to_string())
 }
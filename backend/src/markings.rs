@@ -0,0 +1,195 @@
+use std::collections::HashMap;
+
+use geo::{EuclideanLength, LineString, MultiPolygon, Polygon};
+use serde::Serialize;
+use tsify::Tsify;
+
+use crate::cross_section::num_carriageways;
+use crate::intersection_geometry::intersection_polygon;
+use crate::lanes::{road_lanes, Lane, LaneDirection, LaneKind};
+use crate::math::{first_crossing, offset_band, offset_linestring, slice_linestring};
+use crate::{IntersectionID, MapModel, Road, RoadID};
+
+/// How far stop lines extend along the road, in meters
+const STOP_LINE_THICKNESS: f64 = 0.4;
+
+/// Paint on the road surface, in Mercator
+pub struct Marking {
+    pub kind: MarkingKind,
+    pub pattern: Pattern,
+    pub road: RoadID,
+    pub geometry: MarkingGeometry,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Tsify)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkingKind {
+    /// Between traffic going in opposite directions
+    CentreLine,
+    /// Between lanes going the same way, or next to bus, cycle and parking lanes
+    LaneDivider,
+    /// Along the outer edges of the carriageway
+    EdgeLine,
+    /// Across lanes approaching an intersection
+    StopLine,
+}
+
+#[derive(Clone, Copy, PartialEq, Serialize, Tsify)]
+#[serde(rename_all = "snake_case")]
+pub enum Pattern {
+    Solid,
+    Dashed,
+}
+
+pub enum MarkingGeometry {
+    LineString(LineString),
+    Polygon(Polygon),
+}
+
+/// Generates markings for every road with lanes. Lines stop where the road meets its
+/// intersection polygons, and stop lines are drawn there for roads entering intersections with
+/// more than two roads, not counting footways.
+pub fn all_markings(map: &MapModel) -> Vec<Marking> {
+    let mut intersection_polygons: HashMap<IntersectionID, Option<MultiPolygon>> = HashMap::new();
+    let mut markings = Vec::new();
+    for road in &map.roads {
+        for i in [road.src_i, road.dst_i] {
+            intersection_polygons
+                .entry(i)
+                .or_insert_with(|| intersection_polygon(map, i));
+        }
+        markings.extend(road_markings(
+            map,
+            road,
+            intersection_polygons[&road.src_i].as_ref(),
+            intersection_polygons[&road.dst_i].as_ref(),
+        ));
+    }
+    markings
+}
+
+fn road_markings(
+    map: &MapModel,
+    road: &Road,
+    src_polygon: Option<&MultiPolygon>,
+    dst_polygon: Option<&MultiPolygon>,
+) -> Vec<Marking> {
+    let lanes = road_lanes(road);
+    if lanes.is_empty() {
+        return Vec::new();
+    }
+
+    // Trim the ends of the road that are inside the intersections
+    let length = road.linestring.euclidean_length();
+    let start = src_polygon
+        .and_then(|polygon| first_crossing(&road.linestring, polygon))
+        .unwrap_or(0.0);
+    let end = dst_polygon
+        .and_then(|polygon| {
+            let mut reversed = road.linestring.clone();
+            reversed.0.reverse();
+            first_crossing(&reversed, polygon)
+        })
+        .map(|dist| length - dist)
+        .unwrap_or(length);
+    let Some(trimmed) = slice_linestring(&road.linestring, start, end) else {
+        return Vec::new();
+    };
+
+    let mut markings = Vec::new();
+    let mut add_line = |kind: MarkingKind, pattern: Pattern, offset: f64| {
+        if let Some(linestring) = offset_linestring(&trimmed, offset) {
+            markings.push(Marking {
+                kind,
+                pattern,
+                road: road.id,
+                geometry: MarkingGeometry::LineString(linestring),
+            });
+        }
+    };
+
+    add_line(MarkingKind::EdgeLine, Pattern::Solid, lanes[0].left);
+    add_line(
+        MarkingKind::EdgeLine,
+        Pattern::Solid,
+        lanes[lanes.len() - 1].right,
+    );
+    let driving_lanes = lanes
+        .iter()
+        .filter(|lane| lane.kind == LaneKind::Driving)
+        .count();
+    for pair in lanes.windows(2) {
+        let (kind, pattern) = divider(road, &pair[0], &pair[1], driving_lanes);
+        add_line(kind, pattern, pair[0].right);
+    }
+
+    // Traffic going forwards stops at the end of the road
+    let trimmed_length = end - start;
+    for (i, direction, from, to) in [
+        (
+            road.dst_i,
+            LaneDirection::Forward,
+            trimmed_length - STOP_LINE_THICKNESS,
+            trimmed_length,
+        ),
+        (
+            road.src_i,
+            LaneDirection::Backward,
+            0.0,
+            STOP_LINE_THICKNESS,
+        ),
+    ] {
+        if num_carriageways(map, i) < 3 {
+            continue;
+        }
+        let approaching: Vec<&Lane> = lanes
+            .iter()
+            .filter(|lane| {
+                lane.kind != LaneKind::Parking
+                    && (lane.direction == direction || lane.direction == LaneDirection::Both)
+            })
+            .collect();
+        let (Some(first), Some(last)) = (approaching.first(), approaching.last()) else {
+            continue;
+        };
+        if let Some(polygon) = slice_linestring(&trimmed, from.max(0.0), to)
+            .and_then(|slice| offset_band(&slice, first.left, last.right))
+        {
+            markings.push(Marking {
+                kind: MarkingKind::StopLine,
+                pattern: Pattern::Solid,
+                road: road.id,
+                geometry: MarkingGeometry::Polygon(polygon),
+            });
+        }
+    }
+
+    markings
+}
+
+/// What to paint between two adjacent lanes
+fn divider(road: &Road, left: &Lane, right: &Lane, driving_lanes: usize) -> (MarkingKind, Pattern) {
+    let opposite = matches!(
+        (left.direction, right.direction),
+        (LaneDirection::Backward, LaneDirection::Forward)
+            | (LaneDirection::Forward, LaneDirection::Backward)
+    );
+    if opposite && left.kind == LaneKind::Driving && right.kind == LaneKind::Driving {
+        let pattern = if road.tags.is("overtaking", "no") || driving_lanes >= 4 {
+            Pattern::Solid
+        } else {
+            Pattern::Dashed
+        };
+        return (MarkingKind::CentreLine, pattern);
+    }
+    // Bus and cycle lanes shouldn't be entered
+    let pattern = if [left.kind, right.kind]
+        .iter()
+        .any(|kind| matches!(kind, LaneKind::Bus | LaneKind::Cycle))
+    {
+        Pattern::Solid
+    } else {
+        Pattern::Dashed
+    };
+    (MarkingKind::LaneDivider, pattern)
+}
@@ -20,8 +20,32 @@ pub fn buffer_linestring(
     Some(Polygon::new(LineString(pts), Vec::new()))
 }
 
+/// Shifts a linestring sideways by `offset` meters. Like `buffer_linestring`, negative offsets
+/// are to the left.
+pub fn offset_linestring(linestring: &LineString, offset: f64) -> Option<LineString> {
+    if offset == 0.0 {
+        return Some(linestring.clone());
+    }
+    linestring.offset_curve(offset)
+}
+
+/// Returns the strip between two sideways offsets of a linestring, in meters, with negative
+/// offsets to the left.
+pub fn offset_band(linestring: &LineString, offset1: f64, offset2: f64) -> Option<Polygon> {
+    if offset1 == offset2 {
+        return None;
+    }
+    let mut pts = offset_linestring(linestring, offset1)?.0;
+    let mut other = offset_linestring(linestring, offset2)?.0;
+    other.reverse();
+    pts.extend(other);
+    Some(Polygon::new(LineString(pts), Vec::new()))
+}
+
 pub fn union_all(mut list: Vec<MultiPolygon>) -> MultiPolygon {
-    let mut result = list.pop().unwrap();
+    let Some(mut result) = list.pop() else {
+        return MultiPolygon::new(Vec::new());
+    };
     while let Some(next) = list.pop() {
         result = result.union(&next);
     }
@@ -38,16 +62,20 @@ pub fn project_away(pt: Coord, angle_degrees: f64, distance: f64) -> Coord {
 
 // See also https://github.com/georust/geo/issues/985
 pub fn split_line_by_polygon(line: Line, polygon: &Polygon) -> Option<Line> {
-    // The input line could intersect the polygon's exterior at several places. Find the hit
-    // closest to line.start.
+    // Ignore polygon holes. Assume line.start is outside the polygon and we're looking for the
+    // place it first crosses into the polygon. Callers must check this; see obstacles::cast_ray.
+    split_line_by_linestring(line, polygon.exterior())
+}
+
+/// Returns the part of `line` from its start to the first place it crosses `linestring`
+pub fn split_line_by_linestring(line: Line, linestring: &LineString) -> Option<Line> {
+    // The input line could intersect the linestring at several places. Find the hit closest to
+    // line.start.
     let mut shortest: Option<(Line, f64)> = None;
-    // Ignore polygon holes
-    for polygon_line in polygon.exterior().lines() {
+    for other_line in linestring.lines() {
         if let Some(LineIntersection::SinglePoint { intersection, .. }) =
-            geo::algorithm::line_intersection::line_intersection(line, polygon_line)
+            geo::algorithm::line_intersection::line_intersection(line, other_line)
         {
-            // Assume line.start is outside the polygon and we're looking for the place it first
-            // crosses into the polygon
             let candidate = Line::new(line.start, intersection);
             let candidate_length = candidate.euclidean_length();
             if shortest
@@ -61,3 +89,96 @@ pub fn split_line_by_polygon(line: Line, polygon: &Polygon) -> Option<Line> {
     }
     shortest.map(|pair| pair.0)
 }
+
+/// Returns the piece of a linestring between two distances (in meters) from its start. Returns
+/// None if the slice is empty.
+pub fn slice_linestring(linestring: &LineString, start: f64, end: f64) -> Option<LineString> {
+    if start >= end {
+        return None;
+    }
+    let mut pts = Vec::new();
+    let mut dist_so_far = 0.0;
+    for line in linestring.lines() {
+        let length = line.euclidean_length();
+        let line_start = dist_so_far;
+        let line_end = dist_so_far + length;
+        dist_so_far = line_end;
+        if length == 0.0 || line_end <= start {
+            continue;
+        }
+        if pts.is_empty() {
+            pts.push(interpolate(line, (start - line_start).max(0.0) / length));
+        }
+        if line_end <= end {
+            pts.push(line.end);
+        } else {
+            pts.push(interpolate(line, (end - line_start) / length));
+            break;
+        }
+    }
+    if pts.len() < 2 {
+        return None;
+    }
+    Some(LineString::new(pts))
+}
+
+/// Returns the distance along a linestring (in meters) to where it first crosses the boundary of
+/// any of the polygons
+pub fn first_crossing(linestring: &LineString, polygons: &MultiPolygon) -> Option<f64> {
+    let mut dist_so_far = 0.0;
+    for line in linestring.lines() {
+        let mut closest: Option<f64> = None;
+        for polygon in polygons {
+            for other_line in polygon.exterior().lines() {
+                if let Some(LineIntersection::SinglePoint { intersection, .. }) =
+                    geo::algorithm::line_intersection::line_intersection(line, other_line)
+                {
+                    let dist = Line::new(line.start, intersection).euclidean_length();
+                    if closest.map(|x| dist < x).unwrap_or(true) {
+                        closest = Some(dist);
+                    }
+                }
+            }
+        }
+        if let Some(dist) = closest {
+            return Some(dist_so_far + dist);
+        }
+        dist_so_far += line.euclidean_length();
+    }
+    None
+}
+
+/// Returns the point some distance (in meters) along a linestring, and the direction of the line
+/// there in degrees. Clamps to the ends.
+pub fn point_and_angle_at(linestring: &LineString, dist: f64) -> Option<(Coord, f64)> {
+    let mut dist_so_far = 0.0;
+    let mut last = None;
+    for line in linestring.lines() {
+        let length = line.euclidean_length();
+        if length == 0.0 {
+            continue;
+        }
+        let angle = line.dy().atan2(line.dx()).to_degrees();
+        if dist <= dist_so_far + length {
+            let fraction = ((dist - dist_so_far) / length).max(0.0);
+            return Some((interpolate(line, fraction), angle));
+        }
+        dist_so_far += length;
+        last = Some((line.end, angle));
+    }
+    last
+}
+
+/// Samples a quadratic Bezier curve, including both endpoints.
+pub fn quadratic_bezier(from: Coord, control: Coord, to: Coord, steps: usize) -> Vec<Coord> {
+    (0..=steps)
+        .map(|step| {
+            let t = (step as f64) / (steps as f64);
+            from * ((1.0 - t) * (1.0 - t)) + control * (2.0 * (1.0 - t) * t) + to * (t * t)
+        })
+        .collect()
+}
+
+fn interpolate(line: Line, fraction: f64) -> Coord {
+    line.start + (line.end - line.start) * fraction
+}
@@ -0,0 +1,625 @@
+//! Encodes Mapbox Vector Tiles (https://github.com/mapbox/vector-tile-spec/tree/master/2.1) by
+//! hand. The format is small enough that pulling in a protobuf library isn't worth it.
+
+use std::collections::HashMap;
+use std::f64::consts::PI;
+
+use anyhow::{bail, Result};
+use geo::{
+    BooleanOps, Coord, LineString, MapCoords, MultiLineString, MultiPolygon, Point, Polygon, Rect,
+    Simplify,
+};
+use osm_reader::OsmID;
+
+use crate::intersection_geometry::intersection_polygon;
+use crate::MapModel;
+
+/// Tile coordinates range over [0, EXTENT)
+const EXTENT: u32 = 4096;
+/// Geometry is clipped a bit beyond the tile, so strokes don't get cut off at tile edges
+const BUFFER: f64 = 64.0;
+/// Simplify in tile units, so lower zoom levels lose more detail
+const SIMPLIFY_EPSILON: f64 = 1.0;
+/// Intersection polygons are slow to calculate, so skip them when zoomed far out
+const MIN_ZOOM_INTERSECTION_POLYGONS: u32 = 16;
+/// Deeper than any web map goes, and keeps tile numbers well within u32
+const MAX_ZOOM: u32 = 22;
+
+/// Builds one tile with layers `centerlines`, `road_polygons`, `intersections`,
+/// `intersection_polygons` and `buildings`. Returns None if the tile is empty.
+pub fn make_tile(map: &MapModel, z: u32, x: u32, y: u32) -> Result<Option<Vec<u8>>> {
+    check_zoom(z)?;
+    if x >= 1 << z || y >= 1 << z {
+        bail!("Tile {z}/{x}/{y} doesn't exist");
+    }
+    let tile = TileProjection::new(z, x, y);
+    // Road polygons reach past their centerline's bbox, so pad the query by the widest road
+    let padding = map
+        .roads
+        .iter()
+        .filter_map(|r| Some(r.max_left_width?.max(r.max_right_width?)))
+        .fold(0.0, f64::max);
+    let (roads, mut intersections, buildings) = map
+        .index
+        .bbox_candidates(pad(tile.mercator_bbox(map), padding));
+    // Intersection polygons lie within their roads' polygons, so any reaching into the tile
+    // belongs to one of these roads, even if the intersection's point is outside
+    for r in &roads {
+        let road = &map.roads[r.0];
+        intersections.push(road.src_i);
+        intersections.push(road.dst_i);
+    }
+    intersections.sort();
+    intersections.dedup();
+
+    let mut centerlines = LayerBuilder::new("centerlines");
+    let mut road_polygons = LayerBuilder::new("road_polygons");
+    for r in roads {
+        let road = &map.roads[r.0];
+        let mut attributes = vec![
+            ("id", Value::Uint(r.0 as u64)),
+            ("way", Value::Int(road.way.0)),
+        ];
+        if let Some(highway) = road.tags.get("highway") {
+            attributes.push(("highway", Value::String(highway.clone())));
+        }
+        if let (Some(left), Some(right)) = (road.max_left_width, road.max_right_width) {
+            attributes.push(("max_left_width", Value::Double(left)));
+            attributes.push(("max_right_width", Value::Double(right)));
+        }
+
+        let lines = tile.clip_linestring(map, &road.linestring);
+        centerlines.add_feature(Some(r.0 as u64), &attributes, encode_lines(&lines));
+
+        if let Some(ref polygon) = road.polygon {
+            let polygons = tile.clip_polygon(map, polygon);
+            road_polygons.add_feature(Some(r.0 as u64), &attributes, encode_polygons(&polygons));
+        }
+    }
+
+    let mut intersection_points = LayerBuilder::new("intersections");
+    let mut intersection_polygons = LayerBuilder::new("intersection_polygons");
+    for i in intersections {
+        let intersection = &map.intersections[i.0];
+        let attributes = vec![
+            ("id", Value::Uint(i.0 as u64)),
+            ("node", Value::Int(intersection.node.0)),
+            ("num_roads", Value::Uint(intersection.roads.len() as u64)),
+        ];
+
+        if let Some(pt) = tile.project_point(map, intersection.point) {
+            intersection_points.add_feature(Some(i.0 as u64), &attributes, encode_point(pt));
+        }
+
+        if z >= MIN_ZOOM_INTERSECTION_POLYGONS {
+            if let Some(polygon) = intersection_polygon(map, i) {
+                let polygons = tile.clip_multipolygon(map, &polygon);
+                intersection_polygons.add_feature(
+                    Some(i.0 as u64),
+                    &attributes,
+                    encode_polygons(&polygons),
+                );
+            }
+        }
+    }
+
+    let mut building_polygons = LayerBuilder::new("buildings");
+    for idx in buildings {
+        let b = &map.buildings[idx];
+        let mut attributes = vec![("id", Value::String(b.id.to_string()))];
+        for (k, v) in &b.tags.0 {
+            if k == "building" || k.starts_with("building:") {
+                attributes.push((k.as_str(), Value::String(v.clone())));
+            }
+        }
+        let feature_id = match b.id {
+            OsmID::Way(w) if w.0 > 0 => Some(w.0 as u64),
+            _ => None,
+        };
+        let polygons = tile.clip_polygon(map, &b.polygon);
+        building_polygons.add_feature(feature_id, &attributes, encode_polygons(&polygons));
+    }
+
+    let mut out = Vec::new();
+    for layer in [
+        centerlines,
+        road_polygons,
+        intersection_points,
+        intersection_polygons,
+        building_polygons,
+    ] {
+        if !layer.features.is_empty() {
+            write_bytes(&mut out, 3, &layer.encode());
+        }
+    }
+    if out.is_empty() {
+        Ok(None)
+    } else {
+        Ok(Some(out))
+    }
+}
+
+/// Every tile at zoom `z` overlapping the map's bounds
+pub fn tiles_covering(map: &MapModel, z: u32) -> Result<Vec<(u32, u32)>> {
+    check_zoom(z)?;
+    let bounds = map.mercator.wgs84_bounds;
+    let n = 1 << z;
+    let clamp = |v: f64| (v.floor().max(0.0) as u32).min(n - 1);
+    let (x1, y1) = lon_lat_to_tile(bounds.min().x, bounds.max().y, z);
+    let (x2, y2) = lon_lat_to_tile(bounds.max().x, bounds.min().y, z);
+
+    let mut tiles = Vec::new();
+    for x in clamp(x1)..=clamp(x2) {
+        for y in clamp(y1)..=clamp(y2) {
+            tiles.push((x, y));
+        }
+    }
+    Ok(tiles)
+}
+
+fn check_zoom(z: u32) -> Result<()> {
+    if z > MAX_ZOOM {
+        bail!("Zoom {z} is too deep; the maximum is {MAX_ZOOM}");
+    }
+    Ok(())
+}
+
+/// Returns fractional tile coordinates in Web Mercator
+fn lon_lat_to_tile(lon: f64, lat: f64, z: u32) -> (f64, f64) {
+    let n = 2_f64.powi(z as i32);
+    let lat = lat.to_radians();
+    let x = (lon + 180.0) / 360.0 * n;
+    let y = (1.0 - (lat.tan() + 1.0 / lat.cos()).ln() / PI) / 2.0 * n;
+    (x, y)
+}
+
+fn tile_to_lon_lat(x: f64, y: f64, z: u32) -> Coord {
+    let n = 2_f64.powi(z as i32);
+    let lon = x / n * 360.0 - 180.0;
+    let lat = (PI * (1.0 - 2.0 * y / n)).sinh().atan().to_degrees();
+    Coord { x: lon, y: lat }
+}
+
+/// Grows a Mercator bbox by some meters on every side
+fn pad(bbox: Rect, meters: f64) -> Rect {
+    let offset = Coord {
+        x: meters,
+        y: meters,
+    };
+    Rect::new(bbox.min() - offset, bbox.max() + offset)
+}
+
+/// Transforms from the map's Mercator coordinates into one tile's coordinates
+struct TileProjection {
+    z: u32,
+    x: u32,
+    y: u32,
+    clip: Polygon,
+}
+
+impl TileProjection {
+    fn new(z: u32, x: u32, y: u32) -> Self {
+        let extent = EXTENT as f64;
+        let clip = Rect::new(
+            Coord {
+                x: -BUFFER,
+                y: -BUFFER,
+            },
+            Coord {
+                x: extent + BUFFER,
+                y: extent + BUFFER,
+            },
+        )
+        .to_polygon();
+        Self { z, x, y, clip }
+    }
+
+    /// The tile's bounds, including the buffer
+    fn mercator_bbox(&self, map: &MapModel) -> Rect {
+        let buffer = BUFFER / EXTENT as f64;
+        let corner1 = tile_to_lon_lat(self.x as f64 - buffer, self.y as f64 - buffer, self.z);
+        let corner2 = tile_to_lon_lat(
+            (self.x + 1) as f64 + buffer,
+            (self.y + 1) as f64 + buffer,
+            self.z,
+        );
+        Rect::new(
+            map.mercator.pt_to_mercator(corner1),
+            map.mercator.pt_to_mercator(corner2),
+        )
+    }
+
+    fn project(&self, map: &MapModel, c: Coord) -> Coord {
+        let wgs84 = map.mercator.pt_to_wgs84(c);
+        let (x, y) = lon_lat_to_tile(wgs84.x, wgs84.y, self.z);
+        Coord {
+            x: (x - self.x as f64) * EXTENT as f64,
+            y: (y - self.y as f64) * EXTENT as f64,
+        }
+    }
+
+    fn project_point(&self, map: &MapModel, pt: Point) -> Option<Coord> {
+        let c = self.project(map, pt.into());
+        let extent = EXTENT as f64;
+        if c.x >= 0.0 && c.x < extent && c.y >= 0.0 && c.y < extent {
+            Some(c)
+        } else {
+            None
+        }
+    }
+
+    fn clip_linestring(&self, map: &MapModel, linestring: &LineString) -> MultiLineString {
+        let projected = linestring.map_coords(|c| self.project(map, c));
+        self.clip
+            .clip(&MultiLineString::new(vec![projected]), false)
+            .simplify(&SIMPLIFY_EPSILON)
+    }
+
+    fn clip_polygon(&self, map: &MapModel, polygon: &Polygon) -> MultiPolygon {
+        let projected = polygon.map_coords(|c| self.project(map, c));
+        projected
+            .intersection(&self.clip)
+            .simplify(&SIMPLIFY_EPSILON)
+    }
+
+    fn clip_multipolygon(&self, map: &MapModel, polygons: &MultiPolygon) -> MultiPolygon {
+        let projected = polygons.map_coords(|c| self.project(map, c));
+        projected
+            .intersection(&MultiPolygon::new(vec![self.clip.clone()]))
+            .simplify(&SIMPLIFY_EPSILON)
+    }
+}
+
+#[derive(Clone)]
+enum Value {
+    String(String),
+    Double(f64),
+    Int(i64),
+    Uint(u64),
+}
+
+impl Value {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        match self {
+            Value::String(x) => write_bytes(&mut out, 1, x.as_bytes()),
+            Value::Double(x) => {
+                write_key(&mut out, 3, 1);
+                out.extend_from_slice(&x.to_le_bytes());
+            }
+            Value::Int(x) => {
+                write_key(&mut out, 4, 0);
+                write_varint(&mut out, *x as u64);
+            }
+            Value::Uint(x) => {
+                write_key(&mut out, 5, 0);
+                write_varint(&mut out, *x);
+            }
+        }
+        out
+    }
+}
+
+enum GeomType {
+    Point = 1,
+    LineString = 2,
+    Polygon = 3,
+}
+
+struct LayerBuilder {
+    name: &'static str,
+    keys: Vec<String>,
+    key_lookup: HashMap<String, u32>,
+    // Values are deduplicated by their encoding
+    values: Vec<Vec<u8>>,
+    value_lookup: HashMap<Vec<u8>, u32>,
+    features: Vec<Vec<u8>>,
+}
+
+impl LayerBuilder {
+    fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            keys: Vec::new(),
+            key_lookup: HashMap::new(),
+            values: Vec::new(),
+            value_lookup: HashMap::new(),
+            features: Vec::new(),
+        }
+    }
+
+    /// Skips features with no geometry left after clipping
+    fn add_feature(
+        &mut self,
+        id: Option<u64>,
+        attributes: &[(&str, Value)],
+        geometry: Option<(GeomType, Vec<u32>)>,
+    ) {
+        let Some((geom_type, commands)) = geometry else {
+            return;
+        };
+
+        let mut tags = Vec::new();
+        for (key, value) in attributes {
+            let next_key = self.keys.len() as u32;
+            let key_idx = *self.key_lookup.entry(key.to_string()).or_insert(next_key);
+            if key_idx == next_key {
+                self.keys.push(key.to_string());
+            }
+
+            let encoded = value.encode();
+            let next_value = self.values.len() as u32;
+            let value_idx = *self
+                .value_lookup
+                .entry(encoded.clone())
+                .or_insert(next_value);
+            if value_idx == next_value {
+                self.values.push(encoded);
+            }
+
+            tags.push(key_idx);
+            tags.push(value_idx);
+        }
+
+        let mut feature = Vec::new();
+        if let Some(id) = id {
+            write_key(&mut feature, 1, 0);
+            write_varint(&mut feature, id);
+        }
+        write_packed(&mut feature, 2, &tags);
+        write_key(&mut feature, 3, 0);
+        write_varint(&mut feature, geom_type as u64);
+        write_packed(&mut feature, 4, &commands);
+        self.features.push(feature);
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_bytes(&mut out, 1, self.name.as_bytes());
+        for feature in &self.features {
+            write_bytes(&mut out, 2, feature);
+        }
+        for key in &self.keys {
+            write_bytes(&mut out, 3, key.as_bytes());
+        }
+        for value in &self.values {
+            write_bytes(&mut out, 4, value);
+        }
+        write_key(&mut out, 5, 0);
+        write_varint(&mut out, EXTENT as u64);
+        write_key(&mut out, 15, 0);
+        write_varint(&mut out, 2);
+        out
+    }
+}
+
+/// Geometry commands use deltas from the previous position
+struct CommandWriter {
+    commands: Vec<u32>,
+    cursor: (i32, i32),
+}
+
+impl CommandWriter {
+    fn new() -> Self {
+        Self {
+            commands: Vec::new(),
+            cursor: (0, 0),
+        }
+    }
+
+    fn command(&mut self, id: u32, count: usize) {
+        self.commands.push((id & 0x7) | ((count as u32) << 3));
+    }
+
+    fn point(&mut self, (x, y): (i32, i32)) {
+        self.commands.push(zigzag(x - self.cursor.0));
+        self.commands.push(zigzag(y - self.cursor.1));
+        self.cursor = (x, y);
+    }
+
+    /// Writes a MoveTo then a LineTo through the rest of the points
+    fn path(&mut self, pts: &[(i32, i32)]) {
+        self.command(1, 1);
+        self.point(pts[0]);
+        self.command(2, pts.len() - 1);
+        for pt in &pts[1..] {
+            self.point(*pt);
+        }
+    }
+}
+
+fn encode_point(c: Coord) -> Option<(GeomType, Vec<u32>)> {
+    let mut writer = CommandWriter::new();
+    writer.command(1, 1);
+    writer.point((c.x.round() as i32, c.y.round() as i32));
+    Some((GeomType::Point, writer.commands))
+}
+
+fn encode_lines(lines: &MultiLineString) -> Option<(GeomType, Vec<u32>)> {
+    let mut writer = CommandWriter::new();
+    for line in lines {
+        let pts = round_coords(&line.0);
+        if pts.len() >= 2 {
+            writer.path(&pts);
+        }
+    }
+    if writer.commands.is_empty() {
+        None
+    } else {
+        Some((GeomType::LineString, writer.commands))
+    }
+}
+
+fn encode_polygons(polygons: &MultiPolygon) -> Option<(GeomType, Vec<u32>)> {
+    let mut writer = CommandWriter::new();
+    for polygon in polygons {
+        // If the exterior collapses, skip the holes too
+        let Some(exterior) = ring(polygon.exterior(), true) else {
+            continue;
+        };
+        writer.path(&exterior);
+        writer.command(7, 1);
+        for interior in polygon.interiors() {
+            if let Some(pts) = ring(interior, false) {
+                writer.path(&pts);
+                writer.command(7, 1);
+            }
+        }
+    }
+    if writer.commands.is_empty() {
+        None
+    } else {
+        Some((GeomType::Polygon, writer.commands))
+    }
+}
+
+/// Rounds a ring and drops its closing point. Exterior rings must have a positive area in tile
+/// coordinates (clockwise, since y points down), and interior rings negative.
+fn ring(ring: &LineString, exterior: bool) -> Option<Vec<(i32, i32)>> {
+    let mut pts = round_coords(&ring.0);
+    if pts.len() > 1 && pts[0] == pts[pts.len() - 1] {
+        pts.pop();
+    }
+    if pts.len() < 3 {
+        return None;
+    }
+    let area = signed_area(&pts);
+    if area == 0 {
+        return None;
+    }
+    if (area > 0) != exterior {
+        pts.reverse();
+    }
+    Some(pts)
+}
+
+fn signed_area(pts: &[(i32, i32)]) -> i64 {
+    let mut sum = 0;
+    for (idx, (x1, y1)) in pts.iter().enumerate() {
+        let (x2, y2) = pts[(idx + 1) % pts.len()];
+        sum += *x1 as i64 * y2 as i64 - x2 as i64 * *y1 as i64;
+    }
+    sum
+}
+
+/// Rounds to integers, removing consecutive duplicates
+fn round_coords(coords: &[Coord]) -> Vec<(i32, i32)> {
+    let mut pts: Vec<(i32, i32)> = Vec::new();
+    for c in coords {
+        let pt = (c.x.round() as i32, c.y.round() as i32);
+        if pts.last() != Some(&pt) {
+            pts.push(pt);
+        }
+    }
+    pts
+}
+
+fn zigzag(x: i32) -> u32 {
+    ((x << 1) ^ (x >> 31)) as u32
+}
+
+fn write_key(out: &mut Vec<u8>, field: u32, wire_type: u32) {
+    write_varint(out, ((field << 3) | wire_type) as u64);
+}
+
+fn write_varint(out: &mut Vec<u8>, mut x: u64) {
+    while x >= 0x80 {
+        out.push((x as u8 & 0x7f) | 0x80);
+        x >>= 7;
+    }
+    out.push(x as u8);
+}
+
+fn write_bytes(out: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    write_key(out, field, 2);
+    write_varint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+fn write_packed(out: &mut Vec<u8>, field: u32, values: &[u32]) {
+    let mut packed = Vec::new();
+    for x in values {
+        write_varint(&mut packed, *x as u64);
+    }
+    write_bytes(out, field, &packed);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Examples from section 4.3.5 of the spec
+
+    #[test]
+    fn encode_point_example() {
+        let (_, commands) = encode_point(Coord { x: 25.0, y: 17.0 }).unwrap();
+        assert_eq!(commands, vec![9, 50, 34]);
+    }
+
+    #[test]
+    fn encode_linestring_example() {
+        let lines = MultiLineString::new(vec![LineString::from(vec![
+            (2.0, 2.0),
+            (2.0, 10.0),
+            (10.0, 10.0),
+        ])]);
+        let (_, commands) = encode_lines(&lines).unwrap();
+        assert_eq!(commands, vec![9, 4, 4, 18, 0, 16, 16, 0]);
+    }
+
+    #[test]
+    fn encode_polygon_example() {
+        let polygons = MultiPolygon::new(vec![Polygon::new(
+            LineString::from(vec![(3.0, 6.0), (8.0, 12.0), (20.0, 34.0)]),
+            Vec::new(),
+        )]);
+        let (_, commands) = encode_polygons(&polygons).unwrap();
+        assert_eq!(commands, vec![9, 6, 12, 18, 10, 12, 24, 44, 15]);
+    }
+
+    #[test]
+    fn polygon_winding_is_fixed() {
+        let polygons = MultiPolygon::new(vec![Polygon::new(
+            LineString::from(vec![(3.0, 6.0), (20.0, 34.0), (8.0, 12.0)]),
+            Vec::new(),
+        )]);
+        // Reversed, so it starts from (8, 12)
+        let (_, commands) = encode_polygons(&polygons).unwrap();
+        assert_eq!(commands, vec![9, 16, 24, 18, 24, 44, 33, 55, 15]);
+    }
+
+    #[test]
+    fn zigzag_values() {
+        for (x, expected) in [
+            (0, 0),
+            (-1, 1),
+            (1, 2),
+            (-2, 3),
+            (i32::MAX, u32::MAX - 1),
+            (i32::MIN, u32::MAX),
+        ] {
+            assert_eq!(zigzag(x), expected);
+        }
+    }
+
+    #[test]
+    fn varints() {
+        for (x, expected) in [
+            (0, vec![0x00]),
+            (1, vec![0x01]),
+            (127, vec![0x7f]),
+            (300, vec![0xac, 0x02]),
+        ] {
+            let mut out = Vec::new();
+            write_varint(&mut out, x);
+            assert_eq!(out, expected);
+        }
+    }
+
+    #[test]
+    fn zoom_is_limited() {
+        assert!(check_zoom(MAX_ZOOM).is_ok());
+        assert!(check_zoom(MAX_ZOOM + 1).is_err());
+        assert!(check_zoom(32).is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use std::collections::HashMap;
+
+use geo::{Coord, EuclideanLength, Line, Point};
+use osm_reader::{NodeID, WayID};
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+use utils::Tags;
+
+use crate::{Intersection, Road};
+
+/// Why a node's tags are worth keeping
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeKind {
+    TrafficSignals,
+    Stop,
+    GiveWay,
+    Crossing,
+    TrafficCalming,
+    Barrier,
+    BusStop,
+}
+
+impl NodeKind {
+    /// A node can be several things at once, like a crossing with a barrier
+    pub fn from_tags(tags: &Tags) -> Vec<Self> {
+        let mut kinds = Vec::new();
+        match tags.get("highway").map(|x| x.as_str()) {
+            Some("traffic_signals") => kinds.push(Self::TrafficSignals),
+            Some("stop") => kinds.push(Self::Stop),
+            Some("give_way") => kinds.push(Self::GiveWay),
+            Some("crossing") => kinds.push(Self::Crossing),
+            Some("bus_stop") => kinds.push(Self::BusStop),
+            _ => {}
+        }
+        if tags.has("traffic_calming") && !tags.is("traffic_calming", "no") {
+            kinds.push(Self::TrafficCalming);
+        }
+        if tags.has("barrier") && !tags.is("barrier", "no") {
+            kinds.push(Self::Barrier);
+        }
+        if tags.is("public_transport", "platform")
+            && tags.is("bus", "yes")
+            && !kinds.contains(&Self::BusStop)
+        {
+            kinds.push(Self::BusStop);
+        }
+        kinds
+    }
+}
+
+/// A node with at least one `NodeKind`, found while scraping
+pub struct ScrapedNode {
+    /// WGS84 while scraping, then Mercator
+    pub point: Coord,
+    pub kinds: Vec<NodeKind>,
+    pub tags: Tags,
+}
+
+/// A tagged node somewhere along a road, including at either end
+#[derive(Clone)]
+pub struct RoadNode {
+    pub node: NodeID,
+    pub point: Point,
+    /// Distance from the start of the road, in meters
+    pub dist_along: f64,
+    pub kinds: Vec<NodeKind>,
+    pub tags: Tags,
+}
+
+impl RoadNode {
+    pub fn is(&self, kind: NodeKind) -> bool {
+        self.kinds.contains(&kind)
+    }
+}
+
+/// Finds where the tagged nodes of every OSM way lie along the roads made from it. Roads are
+/// split at intersections, so a node at an intersection is recorded on every road there.
+pub fn assign_road_nodes(
+    roads: &mut [Road],
+    nodes_per_way: &HashMap<WayID, Vec<NodeID>>,
+    tagged_nodes: &HashMap<NodeID, ScrapedNode>,
+) {
+    for road in roads {
+        let Some(nodes) = nodes_per_way.get(&road.way) else {
+            continue;
+        };
+        let mut dist_along = 0.0;
+        for (idx, pt) in road.linestring.0.iter().enumerate() {
+            if idx > 0 {
+                dist_along += Line::new(road.linestring.0[idx - 1], *pt).euclidean_length();
+            }
+            for node in nodes {
+                let tagged = &tagged_nodes[node];
+                if (tagged.point.x - pt.x).abs() < 1e-6 && (tagged.point.y - pt.y).abs() < 1e-6 {
+                    road.nodes.push(RoadNode {
+                        node: *node,
+                        point: Point::from(*pt),
+                        dist_along,
+                        kinds: tagged.kinds.clone(),
+                        tags: tagged.tags.clone(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+pub fn assign_intersection_nodes(
+    intersections: &mut [Intersection],
+    tagged_nodes: &HashMap<NodeID, ScrapedNode>,
+) {
+    for i in intersections {
+        if let Some(tagged) = tagged_nodes.get(&i.node) {
+            i.kinds = tagged.kinds.clone();
+            i.tags = tagged.tags.clone();
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use geo::{
+    BoundingRect, Contains, Coord, EuclideanDistance, EuclideanLength, Intersects, Line,
+    LineString, Polygon,
+};
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+use utils::Tags;
+
+use crate::cross_section::parse_meters;
+use crate::math::{split_line_by_linestring, split_line_by_polygon};
+use crate::nodes::NodeKind;
+use crate::{MapModel, Road};
+
+/// Anything besides a building that can limit how wide a road is
+pub struct Obstacle {
+    pub id: osm_reader::OsmID,
+    pub kind: ObstacleKind,
+    pub geometry: ObstacleGeometry,
+    pub tags: Tags,
+}
+
+pub enum ObstacleGeometry {
+    Polygon(Polygon),
+    LineString(LineString),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, Tsify)]
+#[serde(rename_all = "snake_case")]
+pub enum ObstacleKind {
+    Building,
+    Wall,
+    RetainingWall,
+    Fence,
+    Hedge,
+    Railway,
+    Water,
+    Parking,
+    /// Parks, grass, farmland, and other land that isn't street space
+    Landuse,
+    /// The centerline of a neighbouring road, only used with `stop_at_roads`
+    Road,
+}
+
+/// Probes this close to a bus stop on the road ignore shelters, in meters
+const BUS_STOP_RADIUS: f64 = 15.0;
+
+/// How a ray cast from a road treats an obstacle's boundary
+#[derive(Clone, Copy, PartialEq)]
+pub enum StopRule {
+    /// Nothing past the boundary can be part of the street
+    Always,
+    /// Stop at the boundary, unless the road itself is inside the area. Roads run through parks
+    /// and parking lots, where the boundary doesn't limit them.
+    IfOutside,
+}
+
+impl ObstacleKind {
+    /// Classifies a way that isn't a highway or building
+    pub fn from_tags(tags: &Tags) -> Option<Self> {
+        if let Some(barrier) = tags.get("barrier") {
+            return match barrier.as_str() {
+                "wall" | "city_wall" => Some(Self::Wall),
+                "retaining_wall" => Some(Self::RetainingWall),
+                "fence" => Some(Self::Fence),
+                "hedge" => Some(Self::Hedge),
+                _ => None,
+            };
+        }
+        // Trams usually run along the street, so don't stop there
+        if tags.is_any(
+            "railway",
+            vec!["rail", "light_rail", "subway", "narrow_gauge", "monorail"],
+        ) && !tags.has("tunnel")
+            && !tags.has("bridge")
+        {
+            return Some(Self::Railway);
+        }
+        if tags.is("natural", "water") || tags.is("waterway", "riverbank") {
+            return Some(Self::Water);
+        }
+        if tags.is("amenity", "parking") && !tags.is_any("parking", vec!["street_side", "lane"]) {
+            return Some(Self::Parking);
+        }
+        // These cover whole neighbourhoods including the streets
+        if let Some(landuse) = tags.get("landuse") {
+            if !matches!(
+                landuse.as_str(),
+                "residential" | "commercial" | "industrial" | "retail" | "construction"
+            ) {
+                return Some(Self::Landuse);
+            }
+        }
+        if tags.is_any("leisure", vec!["park", "garden", "playground", "pitch"])
+            || tags.is_any("natural", vec!["wood", "scrub", "grassland"])
+        {
+            return Some(Self::Landuse);
+        }
+        None
+    }
+
+    pub fn stop_rule(self) -> StopRule {
+        match self {
+            Self::Building
+            | Self::Wall
+            | Self::RetainingWall
+            | Self::Fence
+            | Self::Hedge
+            | Self::Railway
+            | Self::Water
+            | Self::Road => StopRule::Always,
+            Self::Parking | Self::Landuse => StopRule::IfOutside,
+        }
+    }
+
+    /// Barriers and railways are lines, even when they form a closed loop
+    pub fn is_linear(self) -> bool {
+        matches!(
+            self,
+            Self::Wall | Self::RetainingWall | Self::Fence | Self::Hedge | Self::Railway
+        )
+    }
+}
+
+impl Obstacle {
+    pub fn new(
+        id: osm_reader::OsmID,
+        kind: ObstacleKind,
+        linestring: LineString,
+        tags: Tags,
+    ) -> Self {
+        let geometry = if kind.is_linear() || !linestring.is_closed() {
+            ObstacleGeometry::LineString(linestring)
+        } else {
+            ObstacleGeometry::Polygon(Polygon::new(linestring, Vec::new()))
+        };
+        Self {
+            id,
+            kind,
+            geometry,
+            tags,
+        }
+    }
+
+    /// True if the point is inside a solid obstacle. Areas like parks don't count, since roads can
+    /// run through them.
+    fn solid_contains(&self, pt: Coord) -> bool {
+        match self.geometry {
+            ObstacleGeometry::Polygon(ref polygon) => {
+                self.kind.stop_rule() == StopRule::Always && polygon.contains(&pt)
+            }
+            ObstacleGeometry::LineString(_) => false,
+        }
+    }
+
+    /// If the ray stops at this obstacle, returns the shortened ray
+    fn stop_ray(&self, ray: Line) -> Option<Line> {
+        match self.geometry {
+            ObstacleGeometry::Polygon(ref polygon) => {
+                stop_ray_at_polygon(ray, polygon, self.kind.stop_rule())
+            }
+            ObstacleGeometry::LineString(ref linestring) => {
+                if linestring.intersects(&ray) {
+                    split_line_by_linestring(ray, linestring)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+fn stop_ray_at_polygon(ray: Line, polygon: &Polygon, rule: StopRule) -> Option<Line> {
+    if !polygon.intersects(&ray) {
+        return None;
+    }
+    if rule == StopRule::IfOutside && polygon.contains(&ray.start) {
+        return None;
+    }
+    split_line_by_polygon(ray, polygon)
+}
+
+pub struct RayCast {
+    /// The shortened ray, its length, and what stopped it
+    pub hit: Option<(Line, f64, ObstacleKind)>,
+    /// The ray started inside a building or other solid obstacle on the road's layer, which was
+    /// ignored. The road probably passes under or through it.
+    pub starts_inside: bool,
+}
+
+/// Finds the closest place a ray cast from `road` stops at any building or obstacle. Obstacles on
+/// a different layer than the road, like a railway under a bridge, are ignored. Buildings are only
+/// ignored when their `layer` tag differs, so abutments still limit a bridge. If the map was built
+/// with `stop_at_roads`, neighbouring roads count too.
+pub fn cast_ray(map: &MapModel, road: &Road, ray: Line) -> RayCast {
+    let road_layer = layer(&road.tags);
+    let road_tagged_layer = tagged_layer(&road.tags);
+    // split_line_by_polygon assumes the ray starts outside the polygon, so skip anything the ray
+    // starts in. That happens for roads through arcades and building passages, and for roads
+    // under buildings without a layer.
+    let mut starts_inside = false;
+
+    let mut best: Option<(Line, f64, ObstacleKind)> = None;
+    let mut consider = |line: Line, kind: ObstacleKind| {
+        let length = line.euclidean_length();
+        if best
+            .as_ref()
+            .map(|(_, best_length, _)| length < *best_length)
+            .unwrap_or(true)
+        {
+            best = Some((line, length, kind));
+        }
+    };
+
+    // Bus shelters stand on the sidewalk, so they don't limit the street
+    let near_bus_stop = road.nodes.iter().any(|n| {
+        n.is(NodeKind::BusStop) && n.point.0.euclidean_distance(&ray.start) < BUS_STOP_RADIUS
+    });
+
+    let bbox = ray.bounding_rect();
+    let (_, _, buildings) = map.index.bbox_candidates(bbox);
+    for b in buildings.into_iter().map(|idx| &map.buildings[idx]) {
+        if tagged_layer(&b.tags) != road_tagged_layer {
+            continue;
+        }
+        if near_bus_stop && (b.tags.is("amenity", "shelter") || b.tags.is("building", "shelter")) {
+            continue;
+        }
+        if b.polygon.contains(&ray.start) {
+            starts_inside = true;
+            continue;
+        }
+        if let Some(line) = stop_ray_at_polygon(ray, &b.polygon, StopRule::Always) {
+            consider(line, ObstacleKind::Building);
+        }
+    }
+    for obstacle in map
+        .index
+        .obstacle_candidates(bbox)
+        .into_iter()
+        .map(|idx| &map.obstacles[idx])
+    {
+        if layer(&obstacle.tags) != road_layer {
+            continue;
+        }
+        if obstacle.solid_contains(ray.start) {
+            starts_inside = true;
+            continue;
+        }
+        if let Some(line) = obstacle.stop_ray(ray) {
+            consider(line, obstacle.kind);
+        }
+    }
+    if map.road_width_options.stop_at_roads {
+        if let Some(line) = stop_ray_at_roads(map, road, ray) {
+            consider(line, ObstacleKind::Road);
+        }
+    }
+    RayCast {
+        hit: best,
+        starts_inside,
+    }
+}
+
+/// The vertical layer of something, using the `layer` tag. Bridges and tunnels without one are
+/// assumed to be one level above or below the ground. Building passages stay on the ground.
+fn layer(tags: &Tags) -> i32 {
+    if let Some(layer) = tags.get("layer").and_then(|x| x.parse::<i32>().ok()) {
+        return layer;
+    }
+    if tags.has("bridge") && !tags.is("bridge", "no") {
+        1
+    } else if tags.has("tunnel") && !tags.is_any("tunnel", vec!["no", "building_passage"]) {
+        -1
+    } else {
+        0
+    }
+}
+
+/// The vertical layer of something, using only the `layer` tag
+fn tagged_layer(tags: &Tags) -> i32 {
+    tags.get("layer")
+        .and_then(|x| x.parse::<i32>().ok())
+        .unwrap_or(0)
+}
+
+/// Stops a ray short of the nearest neighbouring road's centerline it crosses, leaving room for
+/// both roads. The gap between the centerlines is split so that each road gets its own nominal
+/// half-width, then half of whatever's left. Roads sharing an intersection are skipped, since
+/// rays near the ends would otherwise hit them right away.
+fn stop_ray_at_roads(map: &MapModel, road: &Road, ray: Line) -> Option<Line> {
+    let (candidates, _, _) = map.index.bbox_candidates(ray.bounding_rect());
+    let mut best: Option<(Line, f64)> = None;
+    for other in candidates.into_iter().map(|r| &map.roads[r.0]) {
+        if other.id == road.id
+            || [other.src_i, other.dst_i].contains(&road.src_i)
+            || [other.src_i, other.dst_i].contains(&road.dst_i)
+        {
+            continue;
+        }
+        let Some(hit) = split_line_by_linestring(ray, &other.linestring) else {
+            continue;
+        };
+        let dist = hit.euclidean_length();
+        let stop = ((dist + nominal_half_width(&road.tags) - nominal_half_width(&other.tags))
+            / 2.0)
+            .clamp(0.0, dist);
+        if best
+            .as_ref()
+            .map(|(_, best_stop)| stop < *best_stop)
+            .unwrap_or(true)
+        {
+            let fraction = if dist == 0.0 { 0.0 } else { stop / dist };
+            let end = ray.start + (hit.end - ray.start) * fraction;
+            best = Some((Line::new(ray.start, end), stop));
+        }
+    }
+    best.map(|(line, _)| line)
+}
+
+/// How much space a road needs on each side of its centerline, in meters. Uses the `width` tag if
+/// possible, otherwise a guess by class.
+fn nominal_half_width(tags: &Tags) -> f64 {
+    if let Some(width) = tags.get("width").and_then(|w| parse_meters(w)) {
+        return width / 2.0;
+    }
+    if tags.is_any(
+        "highway",
+        vec!["footway", "path", "cycleway", "bridleway", "steps"],
+    ) {
+        1.0
+    } else if tags.is_any("highway", vec!["service", "track"]) {
+        2.0
+    } else {
+        3.0
+    }
+}
@@ -1,31 +1,304 @@
-use geojson::{Feature, Geometry};
+use std::collections::BTreeMap;
 
-use crate::{Building, Intersection, Road};
+use geojson::{Feature, Geometry, JsonObject};
+use serde::Serialize;
+use tsify::Tsify;
+
+use crate::cross_section::SidewalkSource;
+use crate::crossings::Crosswalk;
+use crate::intersection_class::{ControlType, IntersectionKind, Leg};
+use crate::lanes::{Lane, LaneDirection, LaneKind};
+use crate::markings::{Marking, MarkingGeometry, MarkingKind, Pattern};
+use crate::nodes::{NodeKind, RoadNode};
+use crate::obstacles::{Obstacle, ObstacleGeometry, ObstacleKind};
+use crate::{Building, Intersection, IntersectionID, Road, RoadID};
 
 // TODO All of this is totally pointless; just serde serialize all of mapmodel. GJ is not useful
 // for SVG rendering.
 
+// Every feature has a `kind`, so consumers can tell road polygons and buildings apart
+
+#[derive(Serialize, Tsify)]
+pub struct RoadProperties {
+    #[tsify(type = "\"road\"")]
+    kind: &'static str,
+    id: RoadID,
+    way: String,
+    node1: String,
+    node2: String,
+    component: usize,
+    tags: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    max_left_width: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    max_right_width: Option<f64>,
+    /// What limited the width on each side. Missing if the width wasn't limited.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    left_limited_by: Option<ObstacleKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    right_limited_by: Option<ObstacleKind>,
+}
+
+/// The road's buffered polygon, as a separate feature
+#[derive(Serialize, Tsify)]
+pub struct RoadPolygonProperties {
+    #[tsify(type = "\"road_polygon\"")]
+    kind: &'static str,
+    road: RoadID,
+    max_left_width: f64,
+    max_right_width: f64,
+}
+
+/// One band of the road's cross-section
+#[derive(Serialize, Tsify)]
+pub struct RoadBandProperties {
+    #[tsify(type = "\"road_band\"")]
+    kind: &'static str,
+    road: RoadID,
+    #[tsify(type = "\"carriageway\" | \"left_sidewalk\" | \"right_sidewalk\"")]
+    band: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    source: Option<SidewalkSource>,
+    /// In meters
+    width: f64,
+}
+
+/// Each lane has a polygon and a center line, as separate features
+#[derive(Serialize, Tsify)]
+pub struct LaneProperties {
+    #[tsify(type = "\"lane\" | \"lane_center\"")]
+    kind: &'static str,
+    road: RoadID,
+    /// Counting from the left edge of the road
+    index: usize,
+    lane_kind: LaneKind,
+    direction: LaneDirection,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    turn: Option<String>,
+    width: f64,
+}
+
+#[derive(Serialize, Tsify)]
+pub struct MarkingProperties {
+    #[tsify(type = "\"marking\"")]
+    kind: &'static str,
+    road: RoadID,
+    marking: MarkingKind,
+    pattern: Pattern,
+}
+
+/// Zebra crossings have the whole crosswalk and each stripe as separate features
+#[derive(Serialize, Tsify)]
+pub struct CrosswalkProperties {
+    #[tsify(type = "\"crosswalk\" | \"zebra_stripe\"")]
+    kind: &'static str,
+    road: RoadID,
+    /// The OSM crossing node, missing for automatic crosswalks
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    node: Option<String>,
+    /// The `crossing` tag
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    crossing: Option<String>,
+}
+
+#[derive(Serialize, Tsify)]
+pub struct IntersectionProperties {
+    #[tsify(type = "\"intersection\"")]
+    kind: &'static str,
+    id: IntersectionID,
+    node: String,
+    roads: Vec<RoadID>,
+    component: usize,
+    /// From the OSM node's tags
+    kinds: Vec<NodeKind>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    #[tsify(optional)]
+    tags: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    intersection_kind: Option<IntersectionKind>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    #[tsify(optional)]
+    control: Option<ControlType>,
+    /// Ordered clockwise from north
+    legs: Vec<Leg>,
+}
+
+/// A tagged OSM node along a road. Nodes at intersections are also on the intersection.
+#[derive(Serialize, Tsify)]
+pub struct NodeProperties {
+    #[tsify(type = "\"node\"")]
+    kind: &'static str,
+    id: String,
+    road: RoadID,
+    /// Meters from the start of the road
+    dist_along: f64,
+    kinds: Vec<NodeKind>,
+    tags: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Tsify)]
+pub struct BuildingProperties {
+    #[tsify(type = "\"building\"")]
+    kind: &'static str,
+    id: String,
+    tags: BTreeMap<String, String>,
+}
+
+#[derive(Serialize, Tsify)]
+pub struct ObstacleProperties {
+    #[tsify(type = "\"obstacle\"")]
+    kind: &'static str,
+    id: String,
+    class: ObstacleKind,
+    tags: BTreeMap<String, String>,
+}
+
 impl Road {
     pub fn to_geojson(&self) -> Feature {
         let mut f = Feature::from(Geometry::from(&self.linestring));
-        f.set_property("id", self.id.0);
-        f.set_property("way", self.way.to_string());
-        f.set_property("node1", self.node1.to_string());
-        f.set_property("node2", self.node2.to_string());
-        for (k, v) in &self.tags.0 {
-            f.set_property(k, v.to_string());
-        }
+        f.properties = Some(to_properties(&RoadProperties {
+            kind: "road",
+            id: self.id,
+            way: self.way.to_string(),
+            node1: self.node1.to_string(),
+            node2: self.node2.to_string(),
+            component: self.component,
+            tags: tags_to_map(&self.tags),
+            max_left_width: self.max_left_width,
+            max_right_width: self.max_right_width,
+            left_limited_by: self.left_limited_by,
+            right_limited_by: self.right_limited_by,
+        }));
+        f
+    }
 
-        if let Some(max) = self.max_left_width {
-            f.set_property("max_left_width", max);
-        }
-        if let Some(max) = self.max_right_width {
-            f.set_property("max_right_width", max);
+    /// Returns a feature for the road's polygon, if its width has been found
+    pub fn polygon_to_geojson(&self) -> Option<Feature> {
+        let polygon = self.polygon.as_ref()?;
+        let mut f = Feature::from(Geometry::from(polygon));
+        f.properties = Some(to_properties(&RoadPolygonProperties {
+            kind: "road_polygon",
+            road: self.id,
+            max_left_width: self.max_left_width?,
+            max_right_width: self.max_right_width?,
+        }));
+        Some(f)
+    }
+
+    /// Returns features for the carriageway and sidewalk bands, if the cross-section is known
+    pub fn cross_section_to_geojson(&self) -> Vec<Feature> {
+        let Some(ref cross_section) = self.cross_section else {
+            return Vec::new();
+        };
+        let mut features = Vec::new();
+        let mut f = Feature::from(Geometry::from(&cross_section.carriageway));
+        f.properties = Some(to_properties(&RoadBandProperties {
+            kind: "road_band",
+            road: self.id,
+            band: "carriageway",
+            source: None,
+            width: cross_section.carriageway_left + cross_section.carriageway_right,
+        }));
+        features.push(f);
+
+        for (band, sidewalk) in [
+            ("left_sidewalk", &cross_section.left_sidewalk),
+            ("right_sidewalk", &cross_section.right_sidewalk),
+        ] {
+            if let Some(sidewalk) = sidewalk {
+                let mut f = Feature::from(Geometry::from(&sidewalk.polygon));
+                f.properties = Some(to_properties(&RoadBandProperties {
+                    kind: "road_band",
+                    road: self.id,
+                    band,
+                    source: Some(sidewalk.source),
+                    width: sidewalk.outer - sidewalk.inner,
+                }));
+                features.push(f);
+            }
         }
-        if let Some(ref polygon) = self.polygon {
-            // TODO As an object would be better
-            f.set_property("polygon", serde_json::to_string(polygon).unwrap());
+        features
+    }
+}
+
+impl Lane {
+    pub fn to_geojson(&self, road: RoadID, index: usize) -> Vec<Feature> {
+        let mut features = Vec::new();
+        for (kind, geometry) in [
+            ("lane", Geometry::from(&self.polygon)),
+            ("lane_center", Geometry::from(&self.center)),
+        ] {
+            let mut f = Feature::from(geometry);
+            f.properties = Some(to_properties(&LaneProperties {
+                kind,
+                road,
+                index,
+                lane_kind: self.kind,
+                direction: self.direction,
+                turn: self.turn.clone(),
+                width: self.right - self.left,
+            }));
+            features.push(f);
         }
+        features
+    }
+}
+
+impl Marking {
+    pub fn to_geojson(&self) -> Feature {
+        let geometry = match self.geometry {
+            MarkingGeometry::LineString(ref linestring) => Geometry::from(linestring),
+            MarkingGeometry::Polygon(ref polygon) => Geometry::from(polygon),
+        };
+        let mut f = Feature::from(geometry);
+        f.properties = Some(to_properties(&MarkingProperties {
+            kind: "marking",
+            road: self.road,
+            marking: self.kind,
+            pattern: self.pattern,
+        }));
+        f
+    }
+}
+
+impl Crosswalk {
+    pub fn to_geojson(&self) -> Vec<Feature> {
+        std::iter::once(("crosswalk", &self.polygon))
+            .chain(self.stripes.iter().map(|p| ("zebra_stripe", p)))
+            .map(|(kind, polygon)| {
+                let mut f = Feature::from(Geometry::from(polygon));
+                f.properties = Some(to_properties(&CrosswalkProperties {
+                    kind,
+                    road: self.road,
+                    node: self.node.map(|n| n.to_string()),
+                    crossing: self.kind.clone(),
+                }));
+                f
+            })
+            .collect()
+    }
+}
+
+impl RoadNode {
+    pub fn to_geojson(&self, road: RoadID) -> Feature {
+        let mut f = Feature::from(Geometry::from(&self.point));
+        f.properties = Some(to_properties(&NodeProperties {
+            kind: "node",
+            id: self.node.to_string(),
+            road,
+            dist_along: self.dist_along,
+            kinds: self.kinds.clone(),
+            tags: tags_to_map(&self.tags),
+        }));
         f
     }
 }
@@ -33,9 +306,22 @@ impl Road {
 impl Intersection {
     pub fn to_geojson(&self) -> Feature {
         let mut f = Feature::from(Geometry::from(&self.point));
-        f.set_property("id", self.id.0);
-        f.set_property("node", self.node.to_string());
-        f.set_property("roads", self.roads.iter().map(|r| r.0).collect::<Vec<_>>());
+        f.properties = Some(to_properties(&IntersectionProperties {
+            kind: "intersection",
+            id: self.id,
+            node: self.node.to_string(),
+            roads: self.roads.clone(),
+            component: self.component,
+            kinds: self.kinds.clone(),
+            tags: tags_to_map(&self.tags),
+            intersection_kind: self.classification.as_ref().map(|c| c.kind),
+            control: self.classification.as_ref().map(|c| c.control),
+            legs: self
+                .classification
+                .as_ref()
+                .map(|c| c.legs.clone())
+                .unwrap_or_default(),
+        }));
         f
     }
 }
@@ -43,10 +329,42 @@ impl Intersection {
 impl Building {
     pub fn to_geojson(&self) -> Feature {
         let mut f = Feature::from(Geometry::from(&self.polygon));
-        f.set_property("id", self.id.to_string());
-        for (k, v) in &self.tags.0 {
-            f.set_property(k, v.to_string());
-        }
+        f.properties = Some(to_properties(&BuildingProperties {
+            kind: "building",
+            id: self.id.to_string(),
+            tags: tags_to_map(&self.tags),
+        }));
         f
     }
 }
+
+impl Obstacle {
+    pub fn to_geojson(&self) -> Feature {
+        let geometry = match self.geometry {
+            ObstacleGeometry::Polygon(ref polygon) => Geometry::from(polygon),
+            ObstacleGeometry::LineString(ref linestring) => Geometry::from(linestring),
+        };
+        let mut f = Feature::from(geometry);
+        f.properties = Some(to_properties(&ObstacleProperties {
+            kind: "obstacle",
+            id: self.id.to_string(),
+            class: self.kind,
+            tags: tags_to_map(&self.tags),
+        }));
+        f
+    }
+}
+
+pub fn to_properties<T: Serialize>(props: &T) -> JsonObject {
+    match serde_json::to_value(props) {
+        Ok(serde_json::Value::Object(obj)) => obj,
+        _ => unreachable!("properties must serialize to an object"),
+    }
+}
+
+pub fn tags_to_map(tags: &utils::Tags) -> BTreeMap<String, String> {
+    tags.0
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
@@ -0,0 +1,220 @@
+use anyhow::{bail, Result};
+use geo::{LineString, Point, Polygon, Rect};
+use tiny_skia::{Color, FillRule, Paint, Path, PathBuilder, Pixmap, Stroke, StrokeDash, Transform};
+
+use crate::draw::{
+    check_viewbox, draw_map, map_viewbox, BoundsPainter, DrawOptions, Painter, Style,
+};
+use crate::MapModel;
+
+/// Draws the map as a PNG image
+pub fn render_png(map: &MapModel, options: &DrawOptions) -> Result<Vec<u8>> {
+    let mut painter = PngPainter::new(
+        map_viewbox(map, options)?,
+        options.width,
+        options.background.as_deref(),
+    )?;
+    draw_map(map, options, &mut painter);
+    painter.finish()
+}
+
+/// Draws a debug view on top of the surrounding buildings and roads, framed to fit everything in
+/// the debug view
+pub fn render_debug_png(
+    map: &MapModel,
+    width_px: f64,
+    draw_debug: impl Fn(&mut dyn Painter),
+) -> Result<Vec<u8>> {
+    let mut bounds = BoundsPainter::default();
+    draw_debug(&mut bounds);
+    let Some(viewbox) = bounds.bounds(5.0) else {
+        bail!("The debug view is empty");
+    };
+
+    let options = DrawOptions {
+        viewbox: Some([
+            viewbox.min().x,
+            viewbox.min().y,
+            viewbox.max().x,
+            viewbox.max().y,
+        ]),
+        width: width_px,
+        road_polygons: None,
+        intersection_polygons: None,
+        markings: None,
+        crosswalks: None,
+        ..Default::default()
+    };
+    let mut painter = PngPainter::new(viewbox, width_px, options.background.as_deref())?;
+    draw_map(map, &options, &mut painter);
+    draw_debug(&mut painter);
+    painter.finish()
+}
+
+pub struct PngPainter {
+    pixmap: Pixmap,
+    transform: Transform,
+
+    // The current layer's style
+    fill: Option<Paint<'static>>,
+    stroke: Option<(Paint<'static>, Stroke)>,
+    radius: f32,
+}
+
+impl PngPainter {
+    pub fn new(viewbox: Rect, width_px: f64, background: Option<&str>) -> Result<Self> {
+        check_viewbox(viewbox)?;
+        let scale = width_px / viewbox.width();
+        let height_px = viewbox.height() * scale;
+        let Some(mut pixmap) = Pixmap::new(width_px.round() as u32, height_px.round() as u32)
+        else {
+            bail!("Can't make a {width_px}x{height_px} image");
+        };
+        if let Some(color) = background {
+            pixmap.fill(parse_color(color, 1.0));
+        }
+        let transform = Transform::from_row(
+            scale as f32,
+            0.0,
+            0.0,
+            scale as f32,
+            (-viewbox.min().x * scale) as f32,
+            (-viewbox.min().y * scale) as f32,
+        );
+        Ok(Self {
+            pixmap,
+            transform,
+            fill: None,
+            stroke: None,
+            radius: 1.0,
+        })
+    }
+
+    pub fn finish(self) -> Result<Vec<u8>> {
+        Ok(self.pixmap.encode_png()?)
+    }
+
+    fn draw(&mut self, path: Path) {
+        if let Some(ref paint) = self.fill {
+            self.pixmap
+                .fill_path(&path, paint, FillRule::EvenOdd, self.transform, None);
+        }
+        if let Some((ref paint, ref stroke)) = self.stroke {
+            self.pixmap
+                .stroke_path(&path, paint, stroke, self.transform, None);
+        }
+    }
+}
+
+impl Painter for PngPainter {
+    fn begin_layer(&mut self, _: &str, style: &Style) {
+        self.fill = style
+            .fill
+            .as_ref()
+            .map(|color| paint(parse_color(color, style.opacity)));
+        self.stroke = style.stroke.as_ref().map(|color| {
+            let stroke = Stroke {
+                width: style.stroke_width as f32,
+                dash: style.dasharray.as_ref().and_then(|dasharray| {
+                    StrokeDash::new(dasharray.iter().map(|x| *x as f32).collect(), 0.0)
+                }),
+                ..Default::default()
+            };
+            (paint(parse_color(color, style.opacity)), stroke)
+        });
+        self.radius = style.radius as f32;
+    }
+
+    fn end_layer(&mut self) {
+        self.fill = None;
+        self.stroke = None;
+    }
+
+    fn polygon(&mut self, polygon: &Polygon) {
+        let mut pb = PathBuilder::new();
+        for ring in std::iter::once(polygon.exterior()).chain(polygon.interiors()) {
+            add_ring(&mut pb, ring);
+        }
+        if let Some(path) = pb.finish() {
+            self.draw(path);
+        }
+    }
+
+    fn linestring(&mut self, linestring: &LineString) {
+        let mut pb = PathBuilder::new();
+        for (idx, c) in linestring.coords().enumerate() {
+            if idx == 0 {
+                pb.move_to(c.x as f32, c.y as f32);
+            } else {
+                pb.line_to(c.x as f32, c.y as f32);
+            }
+        }
+        if let Some(path) = pb.finish() {
+            // Lines are never filled
+            if let Some((ref paint, ref stroke)) = self.stroke {
+                self.pixmap
+                    .stroke_path(&path, paint, stroke, self.transform, None);
+            }
+        }
+    }
+
+    fn point(&mut self, pt: Point) {
+        if let Some(path) = PathBuilder::from_circle(pt.x() as f32, pt.y() as f32, self.radius) {
+            self.draw(path);
+        }
+    }
+}
+
+fn add_ring(pb: &mut PathBuilder, ring: &LineString) {
+    for (idx, c) in ring.coords().enumerate() {
+        if idx == 0 {
+            pb.move_to(c.x as f32, c.y as f32);
+        } else {
+            pb.line_to(c.x as f32, c.y as f32);
+        }
+    }
+    pb.close();
+}
+
+fn paint(color: Color) -> Paint<'static> {
+    let mut paint = Paint::default();
+    paint.set_color(color);
+    paint.anti_alias = true;
+    paint
+}
+
+/// Understands #rgb, #rrggbb and the color names listed on `Style::fill`
+fn parse_color(color: &str, opacity: f64) -> Color {
+    let rgb = match color {
+        "black" => Some((0, 0, 0)),
+        "white" => Some((255, 255, 255)),
+        "grey" | "gray" => Some((128, 128, 128)),
+        "red" => Some((255, 0, 0)),
+        "green" => Some((0, 128, 0)),
+        "blue" => Some((0, 0, 255)),
+        "yellow" => Some((255, 255, 0)),
+        "orange" => Some((255, 165, 0)),
+        "purple" => Some((128, 0, 128)),
+        "cyan" => Some((0, 255, 255)),
+        "magenta" => Some((255, 0, 255)),
+        "brown" => Some((165, 42, 42)),
+        "pink" => Some((255, 192, 203)),
+        _ => parse_hex(color),
+    };
+    let (r, g, b) = rgb.unwrap_or_else(|| {
+        warn!("Unknown color {color}, using black");
+        (0, 0, 0)
+    });
+    let alpha = (opacity.clamp(0.0, 1.0) * 255.0).round() as u8;
+    Color::from_rgba8(r, g, b, alpha)
+}
+
+fn parse_hex(color: &str) -> Option<(u8, u8, u8)> {
+    let hex = color.strip_prefix('#')?;
+    let digit = |idx: usize, len: usize| u8::from_str_radix(hex.get(idx..idx + len)?, 16).ok();
+    match hex.len() {
+        3 => Some((digit(0, 1)? * 17, digit(1, 1)? * 17, digit(2, 1)? * 17)),
+        6 => Some((digit(0, 2)?, digit(2, 2)?, digit(4, 2)?)),
+        _ => None,
+    }
+}
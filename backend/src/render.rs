@@ -0,0 +1,235 @@
+use std::collections::{BTreeMap, HashSet};
+
+use geo::{Intersects, Rect};
+use geojson::{Feature, GeoJson, Geometry};
+use serde::{Deserialize, Serialize};
+use tsify::Tsify;
+use utils::Tags;
+
+use crate::crossings::all_crosswalks;
+use crate::intersection_geometry::intersection_polygon;
+use crate::lanes::road_lanes;
+use crate::obstacles::{Obstacle, ObstacleGeometry};
+use crate::output::to_properties;
+use crate::{IntersectionID, MapModel};
+
+/// Controls what `MapModel::render_layers` includes. The defaults match the original `render`
+/// output; every newer layer has to be asked for.
+#[derive(Deserialize, Tsify)]
+#[serde(default)]
+pub struct RenderOptions {
+    centerlines: bool,
+    road_polygons: bool,
+    /// The carriageway and sidewalk bands of every road
+    cross_sections: bool,
+    /// Polygons and center lines of every lane, guessed from tags
+    lanes: bool,
+    /// Crosswalks at mapped crossing nodes
+    crosswalks: bool,
+    /// Also add crosswalks where roads enter intersections with more than two roads. Slow, like
+    /// `intersection_polygons`.
+    auto_crosswalks: bool,
+    intersections: bool,
+    /// Tagged OSM nodes along roads, like traffic signals and crossings. A node shared by several
+    /// roads is only included once.
+    nodes: bool,
+    /// These're calculated on demand, so are slow for large areas
+    intersection_polygons: bool,
+    buildings: bool,
+    /// Walls, fences, water, and everything else besides buildings that limits road width
+    obstacles: bool,
+    graph: bool,
+
+    /// Only include roads having all of these keys. If the list of values isn't empty, the tag
+    /// must have one of those values.
+    road_tags: BTreeMap<String, Vec<String>>,
+    /// Like `road_tags`, for buildings
+    building_tags: BTreeMap<String, Vec<String>>,
+    /// Only include features touching this box, given as [x1, y1, x2, y2] in Mercator
+    #[tsify(optional)]
+    bbox: Option<[f64; 4]>,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self {
+            centerlines: true,
+            road_polygons: true,
+            cross_sections: false,
+            lanes: false,
+            crosswalks: false,
+            auto_crosswalks: false,
+            intersections: true,
+            nodes: false,
+            intersection_polygons: false,
+            buildings: true,
+            obstacles: false,
+            graph: false,
+
+            road_tags: BTreeMap::new(),
+            building_tags: BTreeMap::new(),
+            bbox: None,
+        }
+    }
+}
+
+#[derive(Serialize, Tsify)]
+pub struct IntersectionPolygonProperties {
+    #[tsify(type = "\"intersection_polygon\"")]
+    kind: &'static str,
+    intersection: IntersectionID,
+}
+
+pub fn render_layers(map: &MapModel, options: &RenderOptions) -> GeoJson {
+    let bbox = options
+        .bbox
+        .map(|[x1, y1, x2, y2]| Rect::new((x1, y1), (x2, y2)));
+    let bbox = bbox.as_ref();
+
+    let mut features = Vec::new();
+
+    let roads: Vec<_> = map
+        .roads
+        .iter()
+        .filter(|r| matches_tags(&r.tags, &options.road_tags))
+        .collect();
+    if options.centerlines {
+        for r in &roads {
+            if visible(bbox, &r.linestring) {
+                features.push(r.to_geojson());
+            }
+        }
+    }
+    if options.road_polygons {
+        for r in &roads {
+            if let Some(ref polygon) = r.polygon {
+                if visible(bbox, polygon) {
+                    features.extend(r.polygon_to_geojson());
+                }
+            }
+        }
+    }
+    if options.cross_sections {
+        for r in &roads {
+            if let Some(ref cross_section) = r.cross_section {
+                if visible(bbox, &cross_section.carriageway) {
+                    features.extend(r.cross_section_to_geojson());
+                }
+            }
+        }
+    }
+    if options.lanes {
+        for r in &roads {
+            for (idx, lane) in road_lanes(r).into_iter().enumerate() {
+                if visible(bbox, &lane.polygon) {
+                    features.extend(lane.to_geojson(r.id, idx));
+                }
+            }
+        }
+    }
+    if options.crosswalks || options.auto_crosswalks {
+        for crosswalk in all_crosswalks(map, options.auto_crosswalks) {
+            let road = &map.roads[crosswalk.road.0];
+            // Automatic crosswalks are only wanted if they're asked for
+            let wanted = if crosswalk.node.is_some() {
+                options.crosswalks
+            } else {
+                options.auto_crosswalks
+            };
+            if wanted
+                && matches_tags(&road.tags, &options.road_tags)
+                && visible(bbox, &crosswalk.polygon)
+            {
+                features.extend(crosswalk.to_geojson());
+            }
+        }
+    }
+
+    if options.intersections {
+        for i in &map.intersections {
+            if visible(bbox, &i.point) {
+                features.push(i.to_geojson());
+            }
+        }
+    }
+    if options.nodes {
+        let mut seen = HashSet::new();
+        for r in &roads {
+            for node in &r.nodes {
+                if visible(bbox, &node.point) && seen.insert(node.node) {
+                    features.push(node.to_geojson(r.id));
+                }
+            }
+        }
+    }
+    if options.intersection_polygons {
+        for i in &map.intersections {
+            if !visible(bbox, &i.point) {
+                continue;
+            }
+            if let Some(polygon) = intersection_polygon(map, i.id) {
+                let mut f = Feature::from(Geometry::from(&polygon));
+                f.properties = Some(to_properties(&IntersectionPolygonProperties {
+                    kind: "intersection_polygon",
+                    intersection: i.id,
+                }));
+                features.push(f);
+            }
+        }
+    }
+
+    if options.buildings {
+        for b in &map.buildings {
+            if matches_tags(&b.tags, &options.building_tags) && visible(bbox, &b.polygon) {
+                features.push(b.to_geojson());
+            }
+        }
+    }
+
+    if options.obstacles {
+        for o in &map.obstacles {
+            if o.visible(bbox) {
+                features.push(o.to_geojson());
+            }
+        }
+    }
+
+    if options.graph {
+        for f in map.graph.to_features(map, false) {
+            let keep = f
+                .geometry
+                .as_ref()
+                .and_then(|geometry| geo::Geometry::<f64>::try_from(geometry.value.clone()).ok())
+                .map(|geom| visible(bbox, &geom))
+                .unwrap_or(true);
+            if keep {
+                features.push(f);
+            }
+        }
+    }
+
+    GeoJson::from(features)
+}
+
+fn visible<G>(bbox: Option<&Rect>, geom: &G) -> bool
+where
+    Rect: Intersects<G>,
+{
+    bbox.map(|rect| rect.intersects(geom)).unwrap_or(true)
+}
+
+impl Obstacle {
+    fn visible(&self, bbox: Option<&Rect>) -> bool {
+        match self.geometry {
+            ObstacleGeometry::Polygon(ref polygon) => visible(bbox, polygon),
+            ObstacleGeometry::LineString(ref linestring) => visible(bbox, linestring),
+        }
+    }
+}
+
+fn matches_tags(tags: &Tags, filter: &BTreeMap<String, Vec<String>>) -> bool {
+    filter.iter().all(|(k, values)| match tags.get(k) {
+        Some(v) => values.is_empty() || values.contains(v),
+        None => false,
+    })
+}
@@ -0,0 +1,477 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use anyhow::{bail, Result};
+use geo::{
+    BoundingRect, Coord, EuclideanDistance, EuclideanLength, LineLocatePoint, LineString, Point,
+};
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::RTree;
+use serde::Serialize;
+use utils::Tags;
+
+use crate::math::slice_linestring;
+use crate::spatial::{nearest, rectangle};
+use crate::{IntersectionID, MapModel, RoadID};
+
+/// A directed view of either the original roads or the simplified graph, for routing
+pub struct Network {
+    node_points: Vec<Coord>,
+    edges: Vec<NetworkEdge>,
+    // Per node, the edges leaving it, and whether they're traversed forwards
+    outgoing: Vec<Vec<(usize, bool)>>,
+    intersection_to_node: HashMap<IntersectionID, usize>,
+    // Indexes into edges, by their bounding boxes
+    index: RTree<GeomWithData<Rectangle<[f64; 2]>, usize>>,
+}
+
+struct NetworkEdge {
+    node1: usize,
+    node2: usize,
+    linestring: LineString,
+    length: f64,
+    forwards: bool,
+    backwards: bool,
+    roads: Vec<RoadID>,
+}
+
+/// Where a route begins or ends: exactly at a node, or some distance along an edge
+#[derive(Clone, Copy)]
+pub enum Endpoint {
+    Node(usize),
+    Edge { edge: usize, dist: f64 },
+}
+
+#[derive(Serialize)]
+pub struct Route {
+    linestring: LineString,
+    roads: Vec<RoadID>,
+    length: f64,
+}
+
+impl Network {
+    pub fn new() -> Self {
+        Self {
+            node_points: Vec::new(),
+            edges: Vec::new(),
+            outgoing: Vec::new(),
+            intersection_to_node: HashMap::new(),
+            index: RTree::new(),
+        }
+    }
+
+    pub fn from_map(map: &MapModel) -> Self {
+        let mut network = Self::new();
+        for i in &map.intersections {
+            let node = network.add_node(i.point.into());
+            network.intersection_to_node.insert(i.id, node);
+        }
+        for r in &map.roads {
+            let (forwards, backwards) = allowed_directions(&r.tags);
+            network.add_edge(
+                r.src_i.0,
+                r.dst_i.0,
+                r.linestring.clone(),
+                forwards,
+                backwards,
+                vec![r.id],
+            );
+        }
+        network
+    }
+
+    pub fn add_node(&mut self, pt: Coord) -> usize {
+        self.node_points.push(pt);
+        self.outgoing.push(Vec::new());
+        self.node_points.len() - 1
+    }
+
+    /// Records that an intersection from the original map is part of this node
+    pub fn add_intersection(&mut self, i: IntersectionID, node: usize) {
+        self.intersection_to_node.insert(i, node);
+    }
+
+    pub fn add_edge(
+        &mut self,
+        node1: usize,
+        node2: usize,
+        linestring: LineString,
+        forwards: bool,
+        backwards: bool,
+        roads: Vec<RoadID>,
+    ) {
+        let idx = self.edges.len();
+        if forwards {
+            self.outgoing[node1].push((idx, true));
+        }
+        if backwards {
+            self.outgoing[node2].push((idx, false));
+        }
+        if let Some(bbox) = linestring.bounding_rect() {
+            self.index.insert(GeomWithData::new(rectangle(bbox), idx));
+        }
+        self.edges.push(NetworkEdge {
+            node1,
+            node2,
+            length: linestring.euclidean_length(),
+            linestring,
+            forwards,
+            backwards,
+            roads,
+        });
+    }
+
+    pub fn intersection_endpoint(&self, i: IntersectionID) -> Result<Endpoint> {
+        match self.intersection_to_node.get(&i) {
+            Some(node) => Ok(Endpoint::Node(*node)),
+            None => bail!("Unknown {:?}", i),
+        }
+    }
+
+    /// Snaps a point to the closest position along any edge
+    pub fn snap(&self, pt: Coord) -> Result<Endpoint> {
+        let pt = Point::from(pt);
+        let Some((edge, _)) = nearest(&self.index, pt, |e| {
+            pt.euclidean_distance(&self.edges[e].linestring)
+        }) else {
+            bail!("No edges to snap to");
+        };
+        let fraction = self.edges[edge]
+            .linestring
+            .line_locate_point(&pt)
+            .unwrap_or(0.0);
+        Ok(Endpoint::Edge {
+            edge,
+            dist: fraction * self.edges[edge].length,
+        })
+    }
+
+    /// Finds the shortest route between two endpoints with A*, respecting the direction of every
+    /// edge.
+    pub fn route(&self, from: Endpoint, to: Endpoint) -> Result<Route> {
+        // Every way to get from the start onto a node, with the cost and geometry of doing so
+        let mut starts: Vec<(usize, f64, Vec<Piece>)> = Vec::new();
+        match from {
+            Endpoint::Node(node) => starts.push((node, 0.0, Vec::new())),
+            Endpoint::Edge { edge: e, dist } => {
+                let edge = &self.edges[e];
+                if edge.forwards {
+                    let piece = Piece::new(e, dist, edge.length, true);
+                    starts.push((edge.node2, edge.length - dist, vec![piece]));
+                }
+                if edge.backwards {
+                    let piece = Piece::new(e, 0.0, dist, false);
+                    starts.push((edge.node1, dist, vec![piece]));
+                }
+            }
+        }
+
+        // Likewise, every way to get from a node to the end
+        let mut goals: HashMap<usize, (f64, Vec<Piece>)> = HashMap::new();
+        let mut add_goal = |node: usize, cost: f64, pieces: Vec<Piece>| {
+            if goals.get(&node).map(|(c, _)| cost < *c).unwrap_or(true) {
+                goals.insert(node, (cost, pieces));
+            }
+        };
+        match to {
+            Endpoint::Node(node) => add_goal(node, 0.0, Vec::new()),
+            Endpoint::Edge { edge: e, dist } => {
+                let edge = &self.edges[e];
+                if edge.forwards {
+                    add_goal(edge.node1, dist, vec![Piece::new(e, 0.0, dist, true)]);
+                }
+                if edge.backwards {
+                    add_goal(
+                        edge.node2,
+                        edge.length - dist,
+                        vec![Piece::new(e, dist, edge.length, false)],
+                    );
+                }
+            }
+        }
+
+        // The best route found so far
+        let mut best: Option<(f64, Vec<Piece>)> = None;
+
+        // Both ends on the same edge, in the right order
+        if let (Endpoint::Edge { edge: e1, dist: d1 }, Endpoint::Edge { edge: e2, dist: d2 }) =
+            (from, to)
+        {
+            let edge = &self.edges[e1];
+            if e1 == e2 && edge.forwards && d1 <= d2 {
+                best = Some((d2 - d1, vec![Piece::new(e1, d1, d2, true)]));
+            } else if e1 == e2 && edge.backwards && d1 >= d2 {
+                best = Some((d1 - d2, vec![Piece::new(e1, d2, d1, false)]));
+            }
+        }
+
+        let heuristic = |node: usize| {
+            let pt = Point::from(self.node_points[node]);
+            goals
+                .keys()
+                .map(|goal| pt.euclidean_distance(&Point::from(self.node_points[*goal])))
+                .fold(f64::MAX, f64::min)
+        };
+
+        let mut cost_so_far: HashMap<usize, f64> = HashMap::new();
+        // For each node, the node before it, the edge used, and which direction
+        let mut backrefs: HashMap<usize, (usize, usize, bool)> = HashMap::new();
+        let mut start_pieces: HashMap<usize, Vec<Piece>> = HashMap::new();
+        let mut queue = BinaryHeap::new();
+        for (node, cost, pieces) in starts {
+            if cost_so_far.get(&node).map(|c| cost < *c).unwrap_or(true) {
+                cost_so_far.insert(node, cost);
+                start_pieces.insert(node, pieces);
+                queue.push(Item {
+                    priority: cost + heuristic(node),
+                    cost,
+                    node,
+                });
+            }
+        }
+
+        while let Some(Item {
+            priority,
+            cost,
+            node,
+        }) = queue.pop()
+        {
+            if best
+                .as_ref()
+                .map(|(best_cost, _)| priority >= *best_cost)
+                .unwrap_or(false)
+            {
+                break;
+            }
+            if cost > cost_so_far[&node] {
+                continue;
+            }
+
+            if let Some((goal_cost, goal_pieces)) = goals.get(&node) {
+                let total = cost + goal_cost;
+                if best
+                    .as_ref()
+                    .map(|(best_cost, _)| total < *best_cost)
+                    .unwrap_or(true)
+                {
+                    let mut pieces = self.trace_back(node, &backrefs, &start_pieces);
+                    pieces.extend(goal_pieces.iter().cloned());
+                    best = Some((total, pieces));
+                }
+            }
+
+            for (e, forwards) in &self.outgoing[node] {
+                let edge = &self.edges[*e];
+                let next = if *forwards { edge.node2 } else { edge.node1 };
+                let next_cost = cost + edge.length;
+                if cost_so_far
+                    .get(&next)
+                    .map(|c| next_cost < *c)
+                    .unwrap_or(true)
+                {
+                    cost_so_far.insert(next, next_cost);
+                    backrefs.insert(next, (node, *e, *forwards));
+                    // A start reached more cheaply through the network doesn't need its own
+                    // geometry
+                    start_pieces.remove(&next);
+                    queue.push(Item {
+                        priority: next_cost + heuristic(next),
+                        cost: next_cost,
+                        node: next,
+                    });
+                }
+            }
+        }
+
+        let Some((length, pieces)) = best else {
+            bail!("No route found");
+        };
+        self.build_route(length, pieces)
+    }
+
+    fn trace_back(
+        &self,
+        mut node: usize,
+        backrefs: &HashMap<usize, (usize, usize, bool)>,
+        start_pieces: &HashMap<usize, Vec<Piece>>,
+    ) -> Vec<Piece> {
+        let mut pieces = Vec::new();
+        while let Some((prev, e, forwards)) = backrefs.get(&node) {
+            pieces.push(Piece::new(*e, 0.0, self.edges[*e].length, *forwards));
+            node = *prev;
+        }
+        if let Some(first) = start_pieces.get(&node) {
+            pieces.extend(first.iter().rev().cloned());
+        }
+        pieces.reverse();
+        pieces
+    }
+
+    fn build_route(&self, length: f64, pieces: Vec<Piece>) -> Result<Route> {
+        let mut pts: Vec<Coord> = Vec::new();
+        let mut roads: Vec<RoadID> = Vec::new();
+        for piece in pieces {
+            let edge = &self.edges[piece.edge];
+            let Some(mut linestring) = slice_linestring(&edge.linestring, piece.start, piece.end)
+            else {
+                continue;
+            };
+            let mut edge_roads = edge.roads.clone();
+            if !piece.forwards {
+                linestring.0.reverse();
+                edge_roads.reverse();
+            }
+
+            if pts.last() == linestring.0.first() {
+                pts.pop();
+            }
+            pts.extend(linestring.0);
+            for r in edge_roads {
+                if roads.last() != Some(&r) {
+                    roads.push(r);
+                }
+            }
+        }
+
+        if pts.len() < 2 {
+            bail!("The start and end of the route are the same");
+        }
+        Ok(Route {
+            linestring: LineString::new(pts),
+            roads,
+            length,
+        })
+    }
+}
+
+/// Some part of an edge, between two distances from its start
+#[derive(Clone)]
+struct Piece {
+    edge: usize,
+    start: f64,
+    end: f64,
+    forwards: bool,
+}
+
+impl Piece {
+    fn new(edge: usize, start: f64, end: f64, forwards: bool) -> Self {
+        Self {
+            edge,
+            start,
+            end,
+            forwards,
+        }
+    }
+}
+
+/// Returns whether a road can be travelled forwards and backwards, based on its tags
+pub fn allowed_directions(tags: &Tags) -> (bool, bool) {
+    match tags.get("oneway").map(|x| x.as_str()) {
+        Some("yes" | "true" | "1") => (true, false),
+        Some("-1" | "reverse") => (false, true),
+        Some("no" | "false" | "0") => (true, true),
+        _ => {
+            if tags.is("junction", "roundabout")
+                || tags.is("junction", "circular")
+                || tags.is("highway", "motorway")
+            {
+                (true, false)
+            } else {
+                (true, true)
+            }
+        }
+    }
+}
+
+#[derive(PartialEq)]
+struct Item {
+    priority: f64,
+    cost: f64,
+    node: usize,
+}
+
+impl Eq for Item {}
+
+impl PartialOrd for Item {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Item {
+    // BinaryHeap is a max-heap, so reverse the comparison to pop the lowest priority first
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .priority
+            .total_cmp(&self.priority)
+            .then_with(|| other.node.cmp(&self.node))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // a -> b is one-way, b - c is two-way
+    fn network() -> Network {
+        let mut network = Network::new();
+        let a = network.add_node(Coord { x: 0.0, y: 0.0 });
+        let b = network.add_node(Coord { x: 100.0, y: 0.0 });
+        let c = network.add_node(Coord { x: 100.0, y: 100.0 });
+        for (node1, node2, backwards, road) in [(a, b, false, 0), (b, c, true, 1)] {
+            let linestring =
+                LineString::new(vec![network.node_points[node1], network.node_points[node2]]);
+            network.add_edge(
+                node1,
+                node2,
+                linestring,
+                true,
+                backwards,
+                vec![RoadID(road)],
+            );
+        }
+        network
+    }
+
+    #[test]
+    fn route_through_oneway() {
+        let network = network();
+        let route = network.route(Endpoint::Node(0), Endpoint::Node(2)).unwrap();
+        assert_eq!(route.roads, vec![RoadID(0), RoadID(1)]);
+        assert_eq!(route.length, 200.0);
+        assert_eq!(route.linestring.0.len(), 3);
+
+        // Starting partway along the one-way edge
+        let from = network.snap(Coord { x: 50.0, y: 5.0 }).unwrap();
+        let route = network.route(from, Endpoint::Node(2)).unwrap();
+        assert_eq!(route.length, 150.0);
+        assert_eq!(route.linestring.0[0], Coord { x: 50.0, y: 0.0 });
+    }
+
+    #[test]
+    fn no_route_against_oneway() {
+        let network = network();
+        assert!(network.route(Endpoint::Node(2), Endpoint::Node(0)).is_err());
+        let to = network.snap(Coord { x: 50.0, y: 5.0 }).unwrap();
+        assert!(network.route(Endpoint::Node(1), to).is_err());
+    }
+
+    #[test]
+    fn start_equals_end() {
+        let network = network();
+        assert!(network.route(Endpoint::Node(1), Endpoint::Node(1)).is_err());
+        let pt = network.snap(Coord { x: 100.0, y: 50.0 }).unwrap();
+        assert!(network.route(pt, pt).is_err());
+    }
+
+    #[test]
+    fn snap_to_closest_edge() {
+        let network = network();
+        let Endpoint::Edge { edge, dist } = network.snap(Coord { x: 90.0, y: 60.0 }).unwrap()
+        else {
+            panic!("Didn't snap to an edge");
+        };
+        assert_eq!(edge, 1);
+        assert_eq!(dist, 60.0);
+    }
+}
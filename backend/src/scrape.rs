@@ -2,25 +2,62 @@ use std::collections::HashMap;
 
 use anyhow::Result;
 use geo::{Coord, LineString, Polygon};
-use osm_reader::{Element, OsmID};
+use osm_reader::{Element, NodeID, OsmID, WayID};
+use serde::Deserialize;
 use utils::Tags;
 
+use crate::components::{assign_components, remove_small_components};
 use crate::graph::Graph;
+use crate::nodes::{assign_intersection_nodes, assign_road_nodes, NodeKind, ScrapedNode};
+use crate::obstacles::{Obstacle, ObstacleGeometry, ObstacleKind};
+use crate::spatial::SpatialIndex;
 use crate::{Building, Intersection, IntersectionID, MapModel, Road, RoadID};
 
-pub fn scrape_osm(input_bytes: &[u8]) -> Result<MapModel> {
+#[derive(Default, Deserialize)]
+#[serde(default)]
+pub struct Options {
+    /// Drop connected components with fewer roads than this
+    pub min_component_roads: usize,
+    /// Drop connected components whose roads are shorter than this in total, in meters
+    pub min_component_length: f64,
+    pub road_width: crate::find_road_width::Options,
+}
+
+pub fn scrape_osm(input_bytes: &[u8], options: &Options) -> Result<MapModel> {
     let mut node_mapping = HashMap::new();
     let mut highways = Vec::new();
     let mut buildings = Vec::new();
+    let mut obstacles = Vec::new();
+    let mut tagged_nodes: HashMap<NodeID, ScrapedNode> = HashMap::new();
+    let mut nodes_per_way: HashMap<WayID, Vec<NodeID>> = HashMap::new();
     osm_reader::parse(input_bytes, |elem| match elem {
-        Element::Node { id, lon, lat, .. } => {
+        Element::Node {
+            id, lon, lat, tags, ..
+        } => {
             node_mapping.insert(id, Coord { x: lon, y: lat });
+            let tags: Tags = tags.into();
+            let kinds = NodeKind::from_tags(&tags);
+            if !kinds.is_empty() {
+                tagged_nodes.insert(
+                    id,
+                    ScrapedNode {
+                        point: Coord { x: lon, y: lat },
+                        kinds,
+                        tags,
+                    },
+                );
+            }
         }
         Element::Way {
             id, node_ids, tags, ..
         } => {
             let tags: Tags = tags.into();
             if tags.has("highway") {
+                for node in &node_ids {
+                    if tagged_nodes.contains_key(node) {
+                        nodes_per_way.entry(id).or_default().push(*node);
+                    }
+                }
                 highways.push(utils::osm2graph::Way { id, node_ids, tags });
             } else if tags.has("building") {
                 // geo closes the polygon for us
@@ -38,6 +75,15 @@ pub fn scrape_osm(input_bytes: &[u8]) -> Result<MapModel> {
                     polygon,
                     tags,
                 });
+            } else if let Some(kind) = ObstacleKind::from_tags(&tags) {
+                // Ways crossing the edge of a clipped extract refer to missing nodes, so skip those
+                let linestring: LineString = node_ids
+                    .into_iter()
+                    .filter_map(|id| node_mapping.get(&id).cloned())
+                    .collect();
+                if linestring.0.len() >= 2 {
+                    obstacles.push(Obstacle::new(OsmID::Way(id), kind, linestring, tags));
+                }
             }
         }
         Element::Relation { .. } => {}
@@ -46,7 +92,7 @@ pub fn scrape_osm(input_bytes: &[u8]) -> Result<MapModel> {
 
     let osm_graph = utils::osm2graph::Graph::from_scraped_osm(node_mapping, highways);
     // Copy all the fields
-    let intersections: Vec<Intersection> = osm_graph
+    let mut intersections: Vec<Intersection> = osm_graph
         .intersections
         .into_iter()
         .map(|i| Intersection {
@@ -54,11 +100,15 @@ pub fn scrape_osm(input_bytes: &[u8]) -> Result<MapModel> {
             point: i.point,
             node: i.osm_node,
             roads: i.edges.into_iter().map(|e| RoadID(e.0)).collect(),
+            component: 0,
+            kinds: Vec::new(),
+            tags: Tags::default(),
+            classification: None,
         })
         .collect();
 
     // Add in a bit
-    let roads: Vec<Road> = osm_graph
+    let mut roads: Vec<Road> = osm_graph
         .edges
         .into_iter()
         .map(|e| Road {
@@ -70,25 +120,62 @@ pub fn scrape_osm(input_bytes: &[u8]) -> Result<MapModel> {
             node2: e.osm_node2,
             linestring: e.linestring,
             tags: e.osm_tags,
+            component: 0,
             max_left_width: None,
             max_right_width: None,
+            left_limited_by: None,
+            right_limited_by: None,
             polygon: None,
+            cross_section: None,
+            nodes: Vec::new(),
         })
         .collect();
 
+    for node in tagged_nodes.values_mut() {
+        node.point = osm_graph.mercator.pt_to_mercator(node.point);
+    }
+    assign_road_nodes(&mut roads, &nodes_per_way, &tagged_nodes);
+    assign_intersection_nodes(&mut intersections, &tagged_nodes);
+
+    assign_components(&mut roads, &mut intersections);
+    if options.min_component_roads > 0 || options.min_component_length > 0.0 {
+        (roads, intersections) = remove_small_components(
+            roads,
+            intersections,
+            options.min_component_roads,
+            options.min_component_length,
+        );
+    }
+
     for b in &mut buildings {
         osm_graph.mercator.to_mercator_in_place(&mut b.polygon);
     }
+    for obstacle in &mut obstacles {
+        match obstacle.geometry {
+            ObstacleGeometry::Polygon(ref mut polygon) => {
+                osm_graph.mercator.to_mercator_in_place(polygon);
+            }
+            ObstacleGeometry::LineString(ref mut linestring) => {
+                osm_graph.mercator.to_mercator_in_place(linestring);
+            }
+        }
+    }
 
     let graph = Graph::new_from_map(&roads, &intersections);
+    let index = SpatialIndex::new(&roads, &intersections, &buildings, &obstacles);
     let mut map = MapModel {
         mercator: osm_graph.mercator,
         roads,
         intersections,
         buildings,
+        obstacles,
+        index,
+        road_width_options: options.road_width.clone(),
         graph,
         graph_undo_stack: Vec::new(),
     };
     crate::find_road_width::find_all(&mut map);
+    crate::cross_section::find_all(&mut map);
+    crate::intersection_class::classify_all(&mut map);
     Ok(map)
 }
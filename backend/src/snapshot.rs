@@ -0,0 +1,353 @@
+use anyhow::{bail, Result};
+use geo::{LineString, Point, Polygon, Rect};
+use osm_reader::{NodeID, OsmID, RelationID, WayID};
+use serde::{Deserialize, Serialize};
+use utils::{Mercator, Tags};
+
+use crate::cross_section::CrossSection;
+use crate::find_road_width;
+use crate::graph::{Graph, GraphSnapshot};
+use crate::intersection_class::Classification;
+use crate::nodes::{NodeKind, RoadNode};
+use crate::obstacles::{Obstacle, ObstacleGeometry, ObstacleKind};
+use crate::spatial::SpatialIndex;
+use crate::{Building, Intersection, IntersectionID, MapModel, Road, RoadID};
+
+/// Every snapshot starts with this, then the version as a little-endian u32
+const MAGIC: &[u8; 8] = b"CGSNAPSH";
+/// Bump this whenever anything in the snapshot structs changes. Old snapshots can't be loaded;
+/// regenerate them from the OSM input.
+const VERSION: u32 = 8;
+
+// osm-reader and utils types don't implement serde, so the snapshot stores plain copies of
+// everything. The graph's undo stack isn't kept.
+#[derive(Serialize, Deserialize)]
+struct Snapshot {
+    wgs84_bounds: Rect,
+    roads: Vec<RoadSnapshot>,
+    intersections: Vec<IntersectionSnapshot>,
+    buildings: Vec<BuildingSnapshot>,
+    obstacles: Vec<ObstacleSnapshot>,
+    road_width_options: find_road_width::Options,
+    graph: GraphSnapshot,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RoadSnapshot {
+    id: RoadID,
+    way: i64,
+    node1: i64,
+    node2: i64,
+    src_i: IntersectionID,
+    dst_i: IntersectionID,
+    linestring: LineString,
+    tags: Vec<(String, String)>,
+    component: usize,
+    max_left_width: Option<f64>,
+    max_right_width: Option<f64>,
+    left_limited_by: Option<ObstacleKind>,
+    right_limited_by: Option<ObstacleKind>,
+    polygon: Option<Polygon>,
+    cross_section: Option<CrossSection>,
+    nodes: Vec<RoadNodeSnapshot>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct RoadNodeSnapshot {
+    node: i64,
+    point: Point,
+    dist_along: f64,
+    kinds: Vec<NodeKind>,
+    tags: Vec<(String, String)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct IntersectionSnapshot {
+    id: IntersectionID,
+    node: i64,
+    point: Point,
+    roads: Vec<RoadID>,
+    component: usize,
+    kinds: Vec<NodeKind>,
+    tags: Vec<(String, String)>,
+    classification: Option<Classification>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct BuildingSnapshot {
+    id: OsmIDSnapshot,
+    polygon: Polygon,
+    tags: Vec<(String, String)>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ObstacleSnapshot {
+    id: OsmIDSnapshot,
+    kind: ObstacleKind,
+    // Polygons are rebuilt from their closed exterior
+    linestring: LineString,
+    tags: Vec<(String, String)>,
+}
+
+#[derive(Serialize, Deserialize)]
+enum OsmIDSnapshot {
+    Node(i64),
+    Way(i64),
+    Relation(i64),
+}
+
+/// Serializes a fully processed map, so it can be loaded later without repeating any of the work
+pub fn to_snapshot(map: &MapModel) -> Result<Vec<u8>> {
+    let snapshot = Snapshot {
+        wgs84_bounds: map.mercator.wgs84_bounds,
+        roads: map
+            .roads
+            .iter()
+            .map(|r| RoadSnapshot {
+                id: r.id,
+                way: r.way.0,
+                node1: r.node1.0,
+                node2: r.node2.0,
+                src_i: r.src_i,
+                dst_i: r.dst_i,
+                linestring: r.linestring.clone(),
+                tags: tags_to_pairs(&r.tags),
+                component: r.component,
+                max_left_width: r.max_left_width,
+                max_right_width: r.max_right_width,
+                left_limited_by: r.left_limited_by,
+                right_limited_by: r.right_limited_by,
+                polygon: r.polygon.clone(),
+                cross_section: r.cross_section.clone(),
+                nodes: r
+                    .nodes
+                    .iter()
+                    .map(|n| RoadNodeSnapshot {
+                        node: n.node.0,
+                        point: n.point,
+                        dist_along: n.dist_along,
+                        kinds: n.kinds.clone(),
+                        tags: tags_to_pairs(&n.tags),
+                    })
+                    .collect(),
+            })
+            .collect(),
+        intersections: map
+            .intersections
+            .iter()
+            .map(|i| IntersectionSnapshot {
+                id: i.id,
+                node: i.node.0,
+                point: i.point,
+                roads: i.roads.clone(),
+                component: i.component,
+                kinds: i.kinds.clone(),
+                tags: tags_to_pairs(&i.tags),
+                classification: i.classification.clone(),
+            })
+            .collect(),
+        buildings: map
+            .buildings
+            .iter()
+            .map(|b| BuildingSnapshot {
+                id: OsmIDSnapshot::new(b.id),
+                polygon: b.polygon.clone(),
+                tags: tags_to_pairs(&b.tags),
+            })
+            .collect(),
+        obstacles: map
+            .obstacles
+            .iter()
+            .map(|o| ObstacleSnapshot {
+                id: OsmIDSnapshot::new(o.id),
+                kind: o.kind,
+                linestring: match o.geometry {
+                    ObstacleGeometry::Polygon(ref polygon) => polygon.exterior().clone(),
+                    ObstacleGeometry::LineString(ref linestring) => linestring.clone(),
+                },
+                tags: tags_to_pairs(&o.tags),
+            })
+            .collect(),
+        road_width_options: map.road_width_options.clone(),
+        graph: map.graph.to_snapshot(),
+    };
+
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(MAGIC);
+    bytes.extend_from_slice(&VERSION.to_le_bytes());
+    bincode::serialize_into(&mut bytes, &snapshot)?;
+    Ok(bytes)
+}
+
+/// Checks the magic prefix, without validating anything else
+pub fn is_snapshot(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn from_snapshot(bytes: &[u8]) -> Result<MapModel> {
+    if bytes.len() < MAGIC.len() + 4 || !is_snapshot(bytes) {
+        bail!("This isn't a snapshot file");
+    }
+    let version = u32::from_le_bytes(bytes[MAGIC.len()..MAGIC.len() + 4].try_into()?);
+    if version != VERSION {
+        bail!("This snapshot has version {version}, but only version {VERSION} is supported. Regenerate it from the OSM input.");
+    }
+    let snapshot: Snapshot = bincode::deserialize(&bytes[MAGIC.len() + 4..])?;
+
+    let Some(mercator) = Mercator::from(snapshot.wgs84_bounds) else {
+        bail!("The snapshot has invalid bounds");
+    };
+    let roads: Vec<Road> = snapshot
+        .roads
+        .into_iter()
+        .map(|r| Road {
+            id: r.id,
+            way: WayID(r.way),
+            node1: NodeID(r.node1),
+            node2: NodeID(r.node2),
+            src_i: r.src_i,
+            dst_i: r.dst_i,
+            linestring: r.linestring,
+            tags: pairs_to_tags(r.tags),
+            component: r.component,
+            max_left_width: r.max_left_width,
+            max_right_width: r.max_right_width,
+            left_limited_by: r.left_limited_by,
+            right_limited_by: r.right_limited_by,
+            polygon: r.polygon,
+            cross_section: r.cross_section,
+            nodes: r
+                .nodes
+                .into_iter()
+                .map(|n| RoadNode {
+                    node: NodeID(n.node),
+                    point: n.point,
+                    dist_along: n.dist_along,
+                    kinds: n.kinds,
+                    tags: pairs_to_tags(n.tags),
+                })
+                .collect(),
+        })
+        .collect();
+    let intersections: Vec<Intersection> = snapshot
+        .intersections
+        .into_iter()
+        .map(|i| Intersection {
+            id: i.id,
+            node: NodeID(i.node),
+            point: i.point,
+            roads: i.roads,
+            component: i.component,
+            kinds: i.kinds,
+            tags: pairs_to_tags(i.tags),
+            classification: i.classification,
+        })
+        .collect();
+    let buildings: Vec<Building> = snapshot
+        .buildings
+        .into_iter()
+        .map(|b| Building {
+            id: b.id.to_osm(),
+            polygon: b.polygon,
+            tags: pairs_to_tags(b.tags),
+        })
+        .collect();
+    let obstacles: Vec<Obstacle> = snapshot
+        .obstacles
+        .into_iter()
+        .map(|o| Obstacle::new(o.id.to_osm(), o.kind, o.linestring, pairs_to_tags(o.tags)))
+        .collect();
+
+    for r in &roads {
+        if r.src_i.0 >= intersections.len() || r.dst_i.0 >= intersections.len() {
+            bail!("{:?} refers to a missing intersection", r.id);
+        }
+    }
+    for i in &intersections {
+        if i.roads.iter().any(|r| r.0 >= roads.len()) {
+            bail!("{:?} refers to a missing road", i.id);
+        }
+    }
+
+    let graph = Graph::from_snapshot(snapshot.graph)?;
+    let index = SpatialIndex::new(&roads, &intersections, &buildings, &obstacles);
+    Ok(MapModel {
+        mercator,
+        roads,
+        intersections,
+        buildings,
+        obstacles,
+        index,
+        road_width_options: snapshot.road_width_options,
+        graph,
+        graph_undo_stack: Vec::new(),
+    })
+}
+
+impl OsmIDSnapshot {
+    fn new(id: OsmID) -> Self {
+        match id {
+            OsmID::Node(x) => Self::Node(x.0),
+            OsmID::Way(x) => Self::Way(x.0),
+            OsmID::Relation(x) => Self::Relation(x.0),
+        }
+    }
+
+    fn to_osm(&self) -> OsmID {
+        match *self {
+            Self::Node(x) => OsmID::Node(NodeID(x)),
+            Self::Way(x) => OsmID::Way(WayID(x)),
+            Self::Relation(x) => OsmID::Relation(RelationID(x)),
+        }
+    }
+}
+
+fn tags_to_pairs(tags: &Tags) -> Vec<(String, String)> {
+    tags.0.iter().map(|(k, v)| (k.clone(), v.clone())).collect()
+}
+
+fn pairs_to_tags(pairs: Vec<(String, String)>) -> Tags {
+    let mut tags = Tags::default();
+    for (k, v) in pairs {
+        tags.insert(k, v);
+    }
+    tags
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::MapModel;
+
+    // A closed way starting and ending at node 1, with another road leaving from there
+    const LOOP_OSM: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<osm version="0.6">
+  <node id="1" lat="51.5000" lon="-0.1000"/>
+  <node id="2" lat="51.5010" lon="-0.1000"/>
+  <node id="3" lat="51.5010" lon="-0.0990"/>
+  <node id="4" lat="51.4990" lon="-0.1000"/>
+  <way id="10">
+    <nd ref="1"/>
+    <nd ref="2"/>
+    <nd ref="3"/>
+    <nd ref="1"/>
+    <tag k="highway" v="residential"/>
+  </way>
+  <way id="11">
+    <nd ref="1"/>
+    <nd ref="4"/>
+    <tag k="highway" v="residential"/>
+  </way>
+</osm>"#;
+
+    #[test]
+    fn round_trip_with_loop_road() {
+        let map = MapModel::from_osm(LOOP_OSM.as_bytes()).unwrap();
+        assert!(map.roads.iter().any(|r| r.src_i == r.dst_i));
+
+        let bytes = map.save_snapshot().unwrap();
+        let loaded = MapModel::load_snapshot(&bytes).unwrap();
+        assert_eq!(loaded.roads.len(), map.roads.len());
+        assert_eq!(loaded.intersections.len(), map.intersections.len());
+        assert_eq!(loaded.save_snapshot().unwrap(), bytes);
+    }
+}
@@ -0,0 +1,293 @@
+use geo::{
+    BoundingRect, Coord, EuclideanDistance, EuclideanLength, Intersects, LineInterpolatePoint,
+    LineLocatePoint, Point, Polygon, Rect,
+};
+use rstar::primitives::{GeomWithData, Rectangle};
+use rstar::{RTree, AABB};
+use serde::Serialize;
+use tsify::Tsify;
+
+use crate::obstacles::{Obstacle, ObstacleGeometry};
+use crate::{Building, Intersection, IntersectionID, MapModel, Road, RoadID};
+
+/// Indexes the roads, intersections, buildings and obstacles of a map by their bounding boxes.
+/// Everything is in Mercator.
+pub struct SpatialIndex {
+    roads: RTree<GeomWithData<Rectangle<[f64; 2]>, RoadID>>,
+    intersections: RTree<GeomWithData<[f64; 2], IntersectionID>>,
+    // Indexes into MapModel's buildings
+    buildings: RTree<GeomWithData<Rectangle<[f64; 2]>, usize>>,
+    // Indexes into MapModel's obstacles
+    obstacles: RTree<GeomWithData<Rectangle<[f64; 2]>, usize>>,
+}
+
+#[derive(Serialize, Tsify)]
+pub struct NearestRoad {
+    pub road: RoadID,
+    /// In meters
+    pub distance: f64,
+}
+
+#[derive(Serialize, Tsify)]
+pub struct NearestIntersection {
+    pub intersection: IntersectionID,
+    /// In meters
+    pub distance: f64,
+}
+
+#[derive(Serialize, Tsify)]
+pub struct NearestBuilding {
+    /// The OSM ID, matching the building's ID in render output
+    pub building: String,
+    /// In meters
+    pub distance: f64,
+}
+
+/// The closest point on a road to some query point
+#[derive(Serialize, Tsify)]
+pub struct RoadSnap {
+    pub road: RoadID,
+    pub point: Coord,
+    /// In meters
+    pub distance: f64,
+    /// How far along the road's linestring the snapped point is, from 0 to 1
+    pub fraction: f64,
+    /// The same position, in meters from the start of the road
+    pub dist_along: f64,
+}
+
+/// Everything intersecting some area
+#[derive(Serialize, Tsify)]
+pub struct QueryResults {
+    pub roads: Vec<RoadID>,
+    pub intersections: Vec<IntersectionID>,
+    /// OSM IDs, matching the buildings' IDs in render output
+    pub buildings: Vec<String>,
+}
+
+impl SpatialIndex {
+    pub fn new(
+        roads: &[Road],
+        intersections: &[Intersection],
+        buildings: &[Building],
+        obstacles: &[Obstacle],
+    ) -> Self {
+        Self {
+            roads: RTree::bulk_load(
+                roads
+                    .iter()
+                    .filter_map(|r| {
+                        Some(GeomWithData::new(
+                            rectangle(r.linestring.bounding_rect()?),
+                            r.id,
+                        ))
+                    })
+                    .collect(),
+            ),
+            intersections: RTree::bulk_load(
+                intersections
+                    .iter()
+                    .map(|i| GeomWithData::new([i.point.x(), i.point.y()], i.id))
+                    .collect(),
+            ),
+            buildings: RTree::bulk_load(
+                buildings
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(idx, b)| {
+                        Some(GeomWithData::new(
+                            rectangle(b.polygon.bounding_rect()?),
+                            idx,
+                        ))
+                    })
+                    .collect(),
+            ),
+            obstacles: RTree::bulk_load(
+                obstacles
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(idx, o)| {
+                        let bbox = match o.geometry {
+                            ObstacleGeometry::Polygon(ref p) => p.bounding_rect(),
+                            ObstacleGeometry::LineString(ref ls) => ls.bounding_rect(),
+                        };
+                        Some(GeomWithData::new(rectangle(bbox?), idx))
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn nearest_road(&self, map: &MapModel, pt: Coord) -> Option<NearestRoad> {
+        let pt = Point::from(pt);
+        let (road, distance) = nearest(&self.roads, pt, |r| {
+            pt.euclidean_distance(&map.roads[r.0].linestring)
+        })?;
+        Some(NearestRoad { road, distance })
+    }
+
+    pub fn nearest_intersection(&self, pt: Coord) -> Option<NearestIntersection> {
+        let (obj, distance_2) = self
+            .intersections
+            .nearest_neighbor_iter_with_distance_2(&[pt.x, pt.y])
+            .next()?;
+        Some(NearestIntersection {
+            intersection: obj.data,
+            distance: distance_2.sqrt(),
+        })
+    }
+
+    pub fn nearest_building(&self, map: &MapModel, pt: Coord) -> Option<NearestBuilding> {
+        let pt = Point::from(pt);
+        let (idx, distance) = nearest(&self.buildings, pt, |idx| {
+            // Points inside the building have distance 0
+            pt.euclidean_distance(&map.buildings[idx].polygon)
+        })?;
+        Some(NearestBuilding {
+            building: map.buildings[idx].id.to_string(),
+            distance,
+        })
+    }
+
+    pub fn snap_to_road(&self, map: &MapModel, pt: Coord) -> Option<RoadSnap> {
+        let nearest = self.nearest_road(map, pt)?;
+        let linestring = &map.roads[nearest.road.0].linestring;
+        let fraction = linestring.line_locate_point(&Point::from(pt))?;
+        let point = linestring.line_interpolate_point(fraction)?;
+        Some(RoadSnap {
+            road: nearest.road,
+            point: point.into(),
+            distance: nearest.distance,
+            fraction,
+            dist_along: fraction * linestring.euclidean_length(),
+        })
+    }
+
+    pub fn query_bbox(&self, map: &MapModel, bbox: Rect) -> QueryResults {
+        self.query(map, bbox, |geom| bbox.intersects(geom))
+    }
+
+    pub fn query_polygon(&self, map: &MapModel, polygon: &Polygon) -> QueryResults {
+        let Some(bbox) = polygon.bounding_rect() else {
+            return QueryResults {
+                roads: Vec::new(),
+                intersections: Vec::new(),
+                buildings: Vec::new(),
+            };
+        };
+        self.query(map, bbox, |geom| polygon.intersects(geom))
+    }
+
+    /// Everything with a bounding box touching `bbox`, without checking the exact geometry. This is
+    /// cheaper than `query_bbox` for callers that clip geometry anyway. Buildings are returned as
+    /// indices into MapModel's buildings.
+    pub fn bbox_candidates(&self, bbox: Rect) -> (Vec<RoadID>, Vec<IntersectionID>, Vec<usize>) {
+        let envelope =
+            AABB::from_corners([bbox.min().x, bbox.min().y], [bbox.max().x, bbox.max().y]);
+        let mut roads: Vec<RoadID> = self
+            .roads
+            .locate_in_envelope_intersecting(&envelope)
+            .map(|obj| obj.data)
+            .collect();
+        roads.sort();
+        let mut intersections: Vec<IntersectionID> = self
+            .intersections
+            .locate_in_envelope(&envelope)
+            .map(|obj| obj.data)
+            .collect();
+        intersections.sort();
+        let mut buildings: Vec<usize> = self
+            .buildings
+            .locate_in_envelope_intersecting(&envelope)
+            .map(|obj| obj.data)
+            .collect();
+        buildings.sort();
+        (roads, intersections, buildings)
+    }
+
+    /// Indexes into MapModel's obstacles with a bounding box touching `bbox`
+    pub fn obstacle_candidates(&self, bbox: Rect) -> Vec<usize> {
+        let envelope =
+            AABB::from_corners([bbox.min().x, bbox.min().y], [bbox.max().x, bbox.max().y]);
+        let mut obstacles: Vec<usize> = self
+            .obstacles
+            .locate_in_envelope_intersecting(&envelope)
+            .map(|obj| obj.data)
+            .collect();
+        obstacles.sort();
+        obstacles
+    }
+
+    /// Finds everything with a bounding box touching `bbox`, then filters by the exact geometry
+    fn query<F: Fn(&geo::Geometry) -> bool>(
+        &self,
+        map: &MapModel,
+        bbox: Rect,
+        intersects: F,
+    ) -> QueryResults {
+        let envelope =
+            AABB::from_corners([bbox.min().x, bbox.min().y], [bbox.max().x, bbox.max().y]);
+
+        let mut roads: Vec<RoadID> = self
+            .roads
+            .locate_in_envelope_intersecting(&envelope)
+            .map(|obj| obj.data)
+            .filter(|r| intersects(&map.roads[r.0].linestring.clone().into()))
+            .collect();
+        roads.sort();
+
+        let mut intersections: Vec<IntersectionID> = self
+            .intersections
+            .locate_in_envelope(&envelope)
+            .map(|obj| obj.data)
+            .filter(|i| intersects(&map.intersections[i.0].point.into()))
+            .collect();
+        intersections.sort();
+
+        let mut building_indices: Vec<usize> = self
+            .buildings
+            .locate_in_envelope_intersecting(&envelope)
+            .map(|obj| obj.data)
+            .filter(|idx| intersects(&map.buildings[*idx].polygon.clone().into()))
+            .collect();
+        building_indices.sort();
+
+        QueryResults {
+            roads,
+            intersections,
+            buildings: building_indices
+                .into_iter()
+                .map(|idx| map.buildings[idx].id.to_string())
+                .collect(),
+        }
+    }
+}
+
+/// Walks through objects by the distance to their bounding box, calculating the exact distance
+/// until no bounding box could be closer than the best so far
+pub fn nearest<T: Copy, F: Fn(T) -> f64>(
+    tree: &RTree<GeomWithData<Rectangle<[f64; 2]>, T>>,
+    pt: Point,
+    exact_distance: F,
+) -> Option<(T, f64)> {
+    let mut best: Option<(T, f64)> = None;
+    for (obj, bbox_distance_2) in tree.nearest_neighbor_iter_with_distance_2(&[pt.x(), pt.y()]) {
+        if let Some((_, best_distance)) = best {
+            if bbox_distance_2.sqrt() > best_distance {
+                break;
+            }
+        }
+        let distance = exact_distance(obj.data);
+        if best
+            .map(|(_, best_distance)| distance < best_distance)
+            .unwrap_or(true)
+        {
+            best = Some((obj.data, distance));
+        }
+    }
+    best
+}
+
+pub fn rectangle(rect: Rect) -> Rectangle<[f64; 2]> {
+    Rectangle::from_corners([rect.min().x, rect.min().y], [rect.max().x, rect.max().y])
+}
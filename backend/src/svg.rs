@@ -0,0 +1,150 @@
+use std::fmt::Write;
+
+use anyhow::Result;
+use geo::{Coord, LineString, Point, Polygon, Rect};
+
+use crate::draw::{check_viewbox, draw_map, map_viewbox, DrawOptions, Painter, Style};
+use crate::MapModel;
+
+/// Draws the map as an SVG document, in Mercator coordinates
+pub fn render_svg(map: &MapModel, options: &DrawOptions) -> Result<String> {
+    let mut painter = SvgPainter::new(
+        map_viewbox(map, options)?,
+        options.width,
+        options.background.as_deref(),
+    )?;
+    draw_map(map, options, &mut painter);
+    Ok(painter.finish())
+}
+
+pub struct SvgPainter {
+    svg: String,
+    radius: f64,
+}
+
+impl SvgPainter {
+    pub fn new(viewbox: Rect, width_px: f64, background: Option<&str>) -> Result<Self> {
+        check_viewbox(viewbox)?;
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" viewBox="{} {} {} {}" width="{}" height="{}">"#,
+            num(viewbox.min().x),
+            num(viewbox.min().y),
+            num(viewbox.width()),
+            num(viewbox.height()),
+            num(width_px),
+            num(width_px * viewbox.height() / viewbox.width())
+        )
+        .unwrap();
+        if let Some(color) = background {
+            writeln!(
+                svg,
+                r#"<rect x="{}" y="{}" width="{}" height="{}" fill="{}" />"#,
+                num(viewbox.min().x),
+                num(viewbox.min().y),
+                num(viewbox.width()),
+                num(viewbox.height()),
+                escape(color)
+            )
+            .unwrap();
+        }
+        Ok(Self { svg, radius: 1.0 })
+    }
+
+    pub fn finish(mut self) -> String {
+        self.svg.push_str("</svg>\n");
+        self.svg
+    }
+}
+
+impl Painter for SvgPainter {
+    fn begin_layer(&mut self, id: &str, style: &Style) {
+        write!(
+            self.svg,
+            r#"<g id="{}" fill="{}" stroke="{}" stroke-width="{}" opacity="{}""#,
+            escape(id),
+            escape(style.fill.as_deref().unwrap_or("none")),
+            escape(style.stroke.as_deref().unwrap_or("none")),
+            num(style.stroke_width),
+            num(style.opacity)
+        )
+        .unwrap();
+        if let Some(ref dasharray) = style.dasharray {
+            let dasharray: Vec<String> = dasharray.iter().map(|x| num(*x)).collect();
+            write!(self.svg, r#" stroke-dasharray="{}""#, dasharray.join(" ")).unwrap();
+        }
+        self.svg.push_str(">\n");
+        self.radius = style.radius;
+    }
+
+    fn end_layer(&mut self) {
+        self.svg.push_str("</g>\n");
+    }
+
+    fn polygon(&mut self, polygon: &Polygon) {
+        // Use a path, so holes work
+        let mut d = ring_path(polygon.exterior());
+        for interior in polygon.interiors() {
+            d.push(' ');
+            d.push_str(&ring_path(interior));
+        }
+        writeln!(self.svg, r#"<path fill-rule="evenodd" d="{d}" />"#).unwrap();
+    }
+
+    fn linestring(&mut self, linestring: &LineString) {
+        writeln!(
+            self.svg,
+            r#"<polyline fill="none" points="{}" />"#,
+            points(linestring.coords())
+        )
+        .unwrap();
+    }
+
+    fn point(&mut self, pt: Point) {
+        writeln!(
+            self.svg,
+            r#"<circle cx="{}" cy="{}" r="{}" />"#,
+            num(pt.x()),
+            num(pt.y()),
+            num(self.radius)
+        )
+        .unwrap();
+    }
+}
+
+fn ring_path(ring: &LineString) -> String {
+    let mut d = String::new();
+    for (idx, c) in ring.coords().enumerate() {
+        let cmd = if idx == 0 { 'M' } else { 'L' };
+        write!(d, "{cmd}{},{} ", num(c.x), num(c.y)).unwrap();
+    }
+    d.push('Z');
+    d
+}
+
+fn points<'a>(coords: impl Iterator<Item = &'a Coord>) -> String {
+    coords
+        .map(|c| format!("{},{}", num(c.x), num(c.y)))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Centimeter precision is plenty, and keeps the output small and stable
+fn num(x: f64) -> String {
+    let x = (x * 100.0).round() / 100.0;
+    // Avoid "-0"
+    if x == 0.0 {
+        "0".to_string()
+    } else {
+        x.to_string()
+    }
+}
+
+fn escape(value: &str) -> String {
+    value
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -0,0 +1,139 @@
+use wasm_bindgen::prelude::*;
+
+// Methods returning plain JS objects use these, so the .d.ts file has precise return types
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(typescript_type = "RenderOutput")]
+    pub type JsRenderOutput;
+    #[wasm_bindgen(typescript_type = "RoadWidthOutput")]
+    pub type JsRoadWidthOutput;
+    #[wasm_bindgen(typescript_type = "IntersectionGeometryOutput")]
+    pub type JsIntersectionGeometryOutput;
+    #[wasm_bindgen(typescript_type = "RenderedGraph")]
+    pub type JsRenderedGraph;
+    #[wasm_bindgen(typescript_type = "NearestRoad | null")]
+    pub type JsNearestRoad;
+    #[wasm_bindgen(typescript_type = "NearestIntersection | null")]
+    pub type JsNearestIntersection;
+    #[wasm_bindgen(typescript_type = "NearestBuilding | null")]
+    pub type JsNearestBuilding;
+    #[wasm_bindgen(typescript_type = "RoadSnap | null")]
+    pub type JsRoadSnap;
+    #[wasm_bindgen(typescript_type = "QueryResults")]
+    pub type JsQueryResults;
+}
+
+// Most output types derive Tsify, which adds them to the generated .d.ts file. Types from geo and
+// geojson, and the ID wrappers, are declared by hand here. IDs are branded, so TypeScript won't
+// let a RoadID be passed where an IntersectionID is expected.
+#[wasm_bindgen(typescript_custom_section)]
+const TS_TYPES: &str = r#"
+export type RoadID = number & { readonly __brand: "RoadID" };
+export type IntersectionID = number & { readonly __brand: "IntersectionID" };
+export type EdgeID = number & { readonly __brand: "EdgeID" };
+export type NodeID = number & { readonly __brand: "NodeID" };
+
+export interface Coord {
+  x: number;
+  y: number;
+}
+export type Point = Coord;
+export interface Line {
+  start: Coord;
+  end: Coord;
+}
+export type LineString = Coord[];
+export interface Polygon {
+  exterior: LineString;
+  interiors: LineString[];
+}
+export type MultiPolygon = Polygon[];
+
+export type Position = number[];
+export interface GeoJsonPoint {
+  type: "Point";
+  coordinates: Position;
+}
+export interface GeoJsonLineString {
+  type: "LineString";
+  coordinates: Position[];
+}
+export interface GeoJsonPolygon {
+  type: "Polygon";
+  coordinates: Position[][];
+}
+export interface GeoJsonMultiPolygon {
+  type: "MultiPolygon";
+  coordinates: Position[][][];
+}
+export interface Feature<G, P> {
+  type: "Feature";
+  geometry: G;
+  properties: P;
+}
+export interface FeatureCollection<F> {
+  type: "FeatureCollection";
+  features: F[];
+}
+
+export type RoadFeature = Feature<GeoJsonLineString, RoadProperties>;
+export type RoadPolygonFeature = Feature<GeoJsonPolygon, RoadPolygonProperties>;
+export type RoadBandFeature = Feature<GeoJsonPolygon, RoadBandProperties>;
+export type LaneFeature = Feature<
+  GeoJsonPolygon | GeoJsonLineString,
+  LaneProperties
+>;
+export type MarkingFeature = Feature<
+  GeoJsonLineString | GeoJsonPolygon,
+  MarkingProperties
+>;
+export type CrosswalkFeature = Feature<GeoJsonPolygon, CrosswalkProperties>;
+export type IntersectionFeature = Feature<GeoJsonPoint, IntersectionProperties>;
+export type NodeFeature = Feature<GeoJsonPoint, NodeProperties>;
+export type IntersectionPolygonFeature = Feature<
+  GeoJsonMultiPolygon,
+  IntersectionPolygonProperties
+>;
+export type BuildingFeature = Feature<GeoJsonPolygon, BuildingProperties>;
+export type ObstacleFeature = Feature<
+  GeoJsonPolygon | GeoJsonLineString,
+  ObstacleProperties
+>;
+export interface GraphEdgeProperties {
+  kind: "graph_edge";
+  edge: EdgeID;
+  node1: NodeID;
+  node2: NodeID;
+  component: number;
+  length: number;
+  roads: RoadID[];
+  ways: string[];
+  // Tags of all the edge's roads, with differing values joined by ";"
+  tags: Record<string, string>;
+}
+export interface GraphNodeProperties {
+  kind: "graph_node";
+  node: NodeID;
+  component: number;
+  edges: EdgeID[];
+  osm_nodes: string[];
+  intersections: IntersectionID[];
+}
+export type GraphFeature =
+  | Feature<GeoJsonLineString, GraphEdgeProperties>
+  | Feature<GeoJsonPoint, GraphNodeProperties>;
+export type RenderOutput = FeatureCollection<
+  | RoadFeature
+  | RoadPolygonFeature
+  | RoadBandFeature
+  | LaneFeature
+  | MarkingFeature
+  | CrosswalkFeature
+  | IntersectionFeature
+  | NodeFeature
+  | IntersectionPolygonFeature
+  | BuildingFeature
+  | ObstacleFeature
+  | GraphFeature
+>;
+"#;
@@ -0,0 +1,8 @@
+// Makes the browser save a file
+export function download(filename: string, contents: BlobPart) {
+  let a = document.createElement("a");
+  a.href = URL.createObjectURL(new Blob([contents]));
+  a.download = filename;
+  a.click();
+  URL.revokeObjectURL(a.href);
+}
@@ -1,16 +1,18 @@
-export function gjToSvg(points) {
+import type { Line, LineString, Polygon, Position } from "backend";
+
+export function gjToSvg(points: Position[]) {
   return points.map((pt) => `${pt[0]},${pt[1]}`).join(" ");
 }
 
-export function polygonToSvg(polygon) {
+export function polygonToSvg(polygon: Polygon) {
   return linestringToSvg(polygon.exterior);
 }
 
-export function linestringToSvg(linestring) {
+export function linestringToSvg(linestring: LineString) {
   return linestring.map((pt) => `${pt.x},${pt.y}`).join(" ");
 }
 
-export function lineToSvg(line) {
+export function lineToSvg(line: Line) {
   return {
     x1: line.start.x,
     y1: line.start.y,
@@ -1,18 +1,24 @@
-import { MapModel } from "backend";
+import {
+  MapModel,
+  type BuildingFeature,
+  type IntersectionFeature,
+  type RoadFeature,
+} from "backend";
 import { writable, type Writable } from "svelte/store";
 
+export type Feature = RoadFeature | IntersectionFeature | BuildingFeature;
+
 export type Mode =
   | {
       mode: "neutral";
     }
   | {
       mode: "find-width";
-      // TODO gj feature type
-      road: any;
+      road: RoadFeature;
     }
   | {
-      mode: "intersection-polygon";
-      intersection: any;
+      mode: "intersection-geometry";
+      intersection: IntersectionFeature;
     }
   | { mode: "graph" };
 